@@ -0,0 +1,73 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jitters::{rtp::RtpOutStream, rtp_jitter::RtpJitterInStream};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+const PACKETS: usize = 5000;
+
+// 1ms of 44100 mono per packet, shuffled within windows of the given size
+fn packets(window: usize) -> Vec<Vec<u8>> {
+    let mut rtp_out_stream = RtpOutStream::new(1);
+    let mut packets = (0..PACKETS)
+        .map(|_| rtp_out_stream.next_packet(&[0u8; 88]))
+        .collect::<Vec<_>>();
+
+    let mut rng = StdRng::seed_from_u64(0);
+    for chunk in packets[1..].chunks_mut(window) {
+        chunk.shuffle(&mut rng);
+    }
+    packets
+}
+
+fn bench_reorder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reorder");
+    group.throughput(Throughput::Elements(PACKETS as u64));
+
+    // RTP sources throw away anything more than 100 behind the newest packet
+    for &window in &[1, 8, 32, 100] {
+        let packets = packets(window);
+        group.bench_with_input(
+            BenchmarkId::new("next_packet", window),
+            &packets,
+            |b, packets| {
+                b.iter(|| {
+                    let mut rtp_in_jitter_stream = RtpJitterInStream::new(&packets[0]).unwrap();
+                    for packet in &packets[1..] {
+                        rtp_in_jitter_stream.next_packet(packet).unwrap();
+                    }
+                    rtp_in_jitter_stream
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_plc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("plc");
+    group.throughput(Throughput::Elements(PACKETS as u64));
+
+    // every 10th packet is lost, and the rest are reordered
+    let mut packets = packets(16);
+    let first_packet = packets.remove(0);
+    let packets = packets
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| i % 10 != 0)
+        .map(|(_, packet)| packet)
+        .collect::<Vec<_>>();
+
+    group.bench_function("lossy", |b| {
+        b.iter(|| {
+            let mut rtp_in_jitter_stream = RtpJitterInStream::new(&first_packet).unwrap();
+            for packet in &packets {
+                rtp_in_jitter_stream.next_packet(packet).unwrap();
+            }
+            rtp_in_jitter_stream.plc();
+            rtp_in_jitter_stream
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_reorder, bench_plc);
+criterion_main!(benches);
@@ -1,6 +1,5 @@
 #![feature(generators, generator_trait)]
 
-use byteorder::{ByteOrder, NetworkEndian};
 use cpal::{
     self,
     traits::{EventLoopTrait, HostTrait},
@@ -10,46 +9,86 @@ use crossbeam::{
     utils::Backoff,
 };
 use jitters::{
-    rtp::{RtpHeader, JITTERS_MAX_PACKET_SIZE, JITTERS_SAMPLE_RATE},
+    codec::{codec_for, Codec},
+    payload::PayloadFormat,
+    rtp::RTP_MAX_DATAGRAM_SIZE,
     rtp_jitter::RtpJitterInStream,
-    util::samples_to_ms,
 };
 use std::{
     env, mem,
-    mem::size_of,
-    net::UdpSocket,
+    net::{SocketAddr, UdpSocket},
     ops::{Generator, GeneratorState},
     pin::Pin,
     process,
     sync::{Arc, RwLock},
     thread,
+    time::Instant,
 };
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() - 1 != 1 {
-        eprintln!("usage: {} listenhostport", args[0]);
+    if args.len() - 1 != 1 && args.len() - 1 != 3 {
+        eprintln!(
+            "usage: {} listenhostport [payloadtype encoding/clockrate/channels]",
+            args[0]
+        );
         process::exit(-1);
     }
     let listenhostport = String::from(&args[1]);
+
+    // dynamic payload types have to be spelled out, like SDP's a=rtpmap:96 L16/48000/2
+    let payload_format = if args.len() - 1 == 3 {
+        match PayloadFormat::from_rtpmap(args[2].parse().unwrap(), &args[3]) {
+            Ok(payload_format) => Some(payload_format),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(-1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // RTCP on the next port up, by convention
+    let mut rtcp_listen_addr: SocketAddr = listenhostport.parse().unwrap();
+    rtcp_listen_addr.set_port(rtcp_listen_addr.port() + 1);
     let packet_queue = Arc::new(ArrayQueue::<Vec<u8>>::new(1000));
 
     let put_packet_queue = packet_queue.clone(); // "put" ref to the packet_queue
     let putter_thread = thread::spawn(move || {
-        let mut buf = [0u8; JITTERS_MAX_PACKET_SIZE + size_of::<RtpHeader>()];
+        let mut buf = vec![0u8; RTP_MAX_DATAGRAM_SIZE];
         let udp_sock = UdpSocket::bind(listenhostport).unwrap();
         loop {
             let (amt, src) = udp_sock.recv_from(&mut buf).unwrap();
             println!("Received {:#?} bytes from {}:{}", amt, src.ip(), src.port());
-            match put_packet_queue.push(buf.to_vec()) {
+            if amt == buf.len() {
+                println!("Dropping it, it may have been cut off");
+                continue;
+            }
+            match put_packet_queue.push(buf[..amt].to_vec()) {
                 Ok(()) => {}
-                Err(PushError(_)) => return //can't get lock, we done - assume player thread has finished,
+                Err(PushError(_)) => return, //can't get lock, we done - assume player thread has finished,
             }
         }
     });
 
     let rtp_stream: Arc<RwLock<Option<RtpJitterInStream>>> = Arc::new(RwLock::new(None));
 
+    let rtcp_rtp_stream = rtp_stream.clone(); // "rtcp" ref to the RtpJitterInStream
+    let _rtcp_thread = thread::spawn(move || {
+        let mut buf = [0u8; 1500];
+        let udp_sock = UdpSocket::bind(rtcp_listen_addr).unwrap();
+        loop {
+            let (amt, src) = udp_sock.recv_from(&mut buf).unwrap();
+            println!("Received RTCP from {}:{}", src.ip(), src.port());
+            if let Some(ref mut rtp_stream_) = *rtcp_rtp_stream.write().unwrap() {
+                if let Err(err) = rtp_stream_.receive_rtcp(&buf[..amt], Instant::now()) {
+                    eprintln!("Dropping RTCP packet: {}", err);
+                }
+            }
+        }
+    });
+
     let get_packet_queue = packet_queue.clone(); // "get" ref to the packet_queue
     let put_rtp_stream = rtp_stream.clone(); // "put" ref to the RtpJitterInStream
     let getter_thread = thread::spawn(move || {
@@ -60,9 +99,22 @@ fn main() {
                 match get_packet_queue.pop() {
                     Ok(packet) => {
                         if let Some(ref mut rtp_stream_) = *guard {
-                            rtp_stream_.next_packet(&packet);
+                            if let Err(err) = rtp_stream_.next_packet(&packet) {
+                                eprintln!("Dropping packet: {}", err);
+                            }
                         } else {
-                            mem::replace(&mut *guard, Some(RtpJitterInStream::new(&packet)));
+                            let new_stream = match payload_format {
+                                Some(ref payload_format) => {
+                                    RtpJitterInStream::with_format(&packet, payload_format.clone())
+                                }
+                                None => RtpJitterInStream::new(&packet),
+                            };
+                            match new_stream {
+                                Ok(rtp_stream_) => {
+                                    mem::replace(&mut *guard, Some(rtp_stream_));
+                                }
+                                Err(err) => eprintln!("Dropping packet: {}", err),
+                            }
                         }
                         continue 'outer;
                     }
@@ -91,7 +143,11 @@ fn main() {
                     println!("Stream ended - performing plc and playing audio...");
                     rtp_stream_.plc();
 
-                    jitter_stats = rtp_stream_.jitter_stats();
+                    jitter_stats = format!(
+                        "{}, interarrival jitter {:.3}ms",
+                        rtp_stream_.jitter_stats(),
+                        rtp_stream_.interarrival_jitter_ms()
+                    );
 
                     let host = cpal::default_host();
                     let event_loop = host.event_loop();
@@ -101,7 +157,7 @@ fn main() {
 
                     let format = cpal::Format {
                         channels: rtp_stream_.channels as cpal::ChannelCount,
-                        sample_rate: cpal::SampleRate(JITTERS_SAMPLE_RATE),
+                        sample_rate: cpal::SampleRate(rtp_stream_.format().clock_rate()),
                         data_type: cpal::SampleFormat::I16,
                     };
 
@@ -111,29 +167,20 @@ fn main() {
                         .play_stream(stream_id.clone())
                         .expect("couldn't play_stream on event_loop");
 
+                    let mut codec = codec_for(rtp_stream_.format().clone()).unwrap();
+                    let channels = rtp_stream_.channels as usize;
+
                     let mut next_value_generator = || {
-                        for audio_info in rtp_stream_.audio_slices.iter() {
+                        for audio_info in rtp_stream_.audio_slices.values() {
                             let (audio_slice, seq, timestamp) = audio_info;
                             println!(
                                 "Yielding audio slice for sequence {:#?}, timestamp {:#?}ms",
                                 seq,
-                                samples_to_ms(*timestamp as usize, rtp_stream_.channels)
+                                rtp_stream_.format().samples_to_ms(*timestamp as usize)
                             );
-                            for audio_slice_slice in
-                                audio_slice.chunks(2 * rtp_stream_.channels as usize)
-                            {
-                                let mut ret = vec![0i16; rtp_stream_.channels as usize];
-                                match rtp_stream_.channels {
-                                    1 => {
-                                        ret[0] = NetworkEndian::read_i16(&audio_slice_slice);
-                                    }
-                                    2 => {
-                                        ret[0] = NetworkEndian::read_i16(&audio_slice_slice);
-                                        ret[1] = NetworkEndian::read_i16(&audio_slice_slice[2..]);
-                                    }
-                                    _ => panic!("come on bruh"),
-                                }
-                                yield ret;
+                            let pcm = codec.decode(audio_slice);
+                            for frame in pcm.chunks(channels) {
+                                yield frame.to_vec();
                             }
                         }
                         return;
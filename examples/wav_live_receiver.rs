@@ -0,0 +1,243 @@
+use cpal::{
+    self,
+    traits::{EventLoopTrait, HostTrait},
+};
+use crossbeam::{
+    queue::{ArrayQueue, PopError, PushError},
+    utils::Backoff,
+};
+use jitters::{
+    payload::PayloadFormat,
+    rtcp_interval::{RtcpAction, RtcpScheduler},
+    rtp::{JITTERS_SAMPLE_RATE, RTP_MAX_DATAGRAM_SIZE},
+    rtp_jitter::{BufferDepth, OverflowPolicy, RtpJitterInStream},
+};
+use rand::{thread_rng, Rng};
+use std::{
+    collections::VecDeque,
+    env,
+    net::{SocketAddr, UdpSocket},
+    process,
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+const SESSION_BANDWIDTH: f64 = JITTERS_SAMPLE_RATE as f64 * 4.0; // 16-bit stereo at most
+const MIN_READ_TIMEOUT: Duration = Duration::from_millis(10);
+const MAX_BUFFER_MS: f64 = 2000.0; // if we fall this far behind, skip ahead
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() - 1 != 1 && args.len() - 1 != 3 {
+        eprintln!(
+            "usage: {} listenhostport [payloadtype encoding/clockrate/channels]",
+            args[0]
+        );
+        process::exit(-1);
+    }
+    let listenhostport = String::from(&args[1]);
+
+    // dynamic payload types have to be spelled out, like SDP's a=rtpmap:96 L16/48000/2
+    let payload_format = if args.len() - 1 == 3 {
+        match PayloadFormat::from_rtpmap(args[2].parse().unwrap(), &args[3]) {
+            Ok(payload_format) => Some(payload_format),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(-1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // RTCP on the next port up, by convention
+    let mut rtcp_listen_addr: SocketAddr = listenhostport.parse().unwrap();
+    rtcp_listen_addr.set_port(rtcp_listen_addr.port() + 1);
+    let packet_queue = Arc::new(ArrayQueue::<Vec<u8>>::new(1000));
+
+    let put_packet_queue = packet_queue.clone(); // "put" ref to the packet_queue
+    let putter_thread = thread::spawn(move || {
+        let mut buf = vec![0u8; RTP_MAX_DATAGRAM_SIZE];
+        let udp_sock = UdpSocket::bind(listenhostport).unwrap();
+        loop {
+            let (amt, src) = udp_sock.recv_from(&mut buf).unwrap();
+            println!("Received {:#?} bytes from {}:{}", amt, src.ip(), src.port());
+            if amt == buf.len() {
+                println!("Dropping it, it may have been cut off");
+                continue;
+            }
+            match put_packet_queue.push(buf[..amt].to_vec()) {
+                Ok(()) => {}
+                Err(PushError(_)) => return, //can't get lock, we done - assume player thread has finished
+            }
+        }
+    });
+
+    let rtp_stream: Arc<RwLock<Option<RtpJitterInStream>>> = Arc::new(RwLock::new(None));
+
+    let get_packet_queue = packet_queue.clone(); // "get" ref to the packet_queue
+    let put_rtp_stream = rtp_stream.clone(); // "put" ref to the RtpJitterInStream
+    let getter_thread = thread::spawn(move || loop {
+        let backoff = Backoff::new();
+        match get_packet_queue.pop() {
+            Ok(packet) => {
+                let mut guard = put_rtp_stream.write().unwrap();
+                if let Some(ref mut rtp_stream_) = *guard {
+                    if let Err(err) = rtp_stream_.next_packet(&packet) {
+                        eprintln!("Dropping packet: {}", err);
+                    }
+                } else {
+                    let new_stream = match payload_format {
+                        Some(ref payload_format) => {
+                            RtpJitterInStream::with_format(&packet, payload_format.clone())
+                        }
+                        None => RtpJitterInStream::new(&packet),
+                    };
+                    match new_stream {
+                        Ok(mut rtp_stream_) => {
+                            rtp_stream_.set_max_depth(
+                                Some(BufferDepth::Ms(MAX_BUFFER_MS)),
+                                OverflowPolicy::DropOldest,
+                            );
+                            *guard = Some(rtp_stream_);
+                        }
+                        Err(err) => eprintln!("Dropping packet: {}", err),
+                    }
+                }
+            }
+            Err(PopError) => backoff.snooze(),
+        }
+    });
+
+    let rtcp_rtp_stream = rtp_stream.clone(); // "rtcp" ref to the RtpJitterInStream
+    let _rtcp_thread = thread::spawn(move || {
+        let our_ssrc = thread_rng().gen::<u32>();
+        let mut buf = [0u8; 1500];
+        let udp_sock = UdpSocket::bind(rtcp_listen_addr).unwrap();
+
+        let mut sender_addr: Option<SocketAddr> = None;
+        let mut rtcp_scheduler = RtcpScheduler::new(SESSION_BANDWIDTH, Instant::now());
+        rtcp_scheduler.set_members(2, 1); // us and the sender
+
+        loop {
+            let timeout = rtcp_scheduler
+                .next_transmission()
+                .saturating_duration_since(Instant::now());
+            udp_sock
+                .set_read_timeout(Some(timeout.max(MIN_READ_TIMEOUT)))
+                .unwrap();
+
+            if let Ok((amt, src)) = udp_sock.recv_from(&mut buf) {
+                rtcp_scheduler.packet_received(amt);
+                println!("Received RTCP from {}", src);
+                sender_addr = Some(src);
+                if let Some(ref mut rtp_stream_) = *rtcp_rtp_stream.write().unwrap() {
+                    if let Err(err) = rtp_stream_.receive_rtcp(&buf[..amt], Instant::now()) {
+                        eprintln!("Dropping RTCP packet: {}", err);
+                    }
+                }
+            }
+
+            if rtcp_scheduler.poll(Instant::now()) != RtcpAction::SendReport {
+                continue;
+            }
+
+            if let (Some(sender_addr_), Some(ref mut rtp_stream_)) =
+                (sender_addr, &mut *rtcp_rtp_stream.write().unwrap())
+            {
+                println!("Sending RTCP RR to {}", sender_addr_);
+                let rr = rtp_stream_.receiver_report(our_ssrc, Instant::now());
+                udp_sock.send_to(&rr, sender_addr_).unwrap();
+                rtcp_scheduler.packet_sent(rr.len(), Instant::now());
+            }
+        }
+    });
+
+    let play_rtp_stream = rtp_stream.clone(); // "play" ref to the RtpJitterInStream
+    let player_thread = thread::spawn(move || {
+        // unlike wav_jitter_receiver we don't wait for the end of the stream,
+        // just for the first packet so we know the format to play
+        let payload_format = loop {
+            let backoff = Backoff::new();
+            if let Some(ref rtp_stream_) = *play_rtp_stream.read().unwrap() {
+                break rtp_stream_.format().clone();
+            }
+            backoff.snooze();
+        };
+
+        println!("Stream started - playing audio live...");
+
+        let host = cpal::default_host();
+        let event_loop = host.event_loop();
+        let device = host
+            .default_output_device()
+            .expect("no output device available");
+
+        let format = cpal::Format {
+            channels: payload_format.channels() as cpal::ChannelCount,
+            sample_rate: cpal::SampleRate(payload_format.clock_rate()),
+            data_type: cpal::SampleFormat::I16,
+        };
+
+        let stream_id = event_loop.build_output_stream(&device, &format).unwrap();
+
+        event_loop
+            .play_stream(stream_id.clone())
+            .expect("couldn't play_stream on event_loop");
+
+        let mut pending: VecDeque<i16> = VecDeque::new();
+
+        event_loop.run(move |id, result| {
+            let data = match result {
+                Ok(data) => data,
+                Err(err) => {
+                    eprintln!("an error occurred on stream {:?}: {}", id, err);
+                    return;
+                }
+            };
+
+            if let cpal::StreamData::Output {
+                buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer),
+            } = data
+            {
+                for out in buffer.iter_mut() {
+                    if pending.is_empty() {
+                        let mut guard = play_rtp_stream.write().unwrap();
+                        let rtp_stream_ = guard.as_mut().unwrap();
+
+                        match rtp_stream_.pop_frame(Instant::now()) {
+                            Some((audio_slice, seq, timestamp)) => {
+                                println!(
+                                    "Yielding audio slice for sequence {:#?}, timestamp {:#?}ms (buffered {:.1}ms, target delay {:.1}ms)",
+                                    seq,
+                                    payload_format.samples_to_ms(timestamp as usize),
+                                    rtp_stream_.buffer_depth_ms(),
+                                    rtp_stream_.target_delay_ms()
+                                );
+                                pending.extend(rtp_stream_.decode(&audio_slice));
+                            }
+                            None => {
+                                if rtp_stream_.ended() && rtp_stream_.audio_slices.is_empty() {
+                                    println!("audio done, exiting program");
+                                    println!(
+                                        "Jitter stream stats: {:#?}, interarrival jitter {:.3}ms",
+                                        rtp_stream_.jitter_stats(),
+                                        rtp_stream_.interarrival_jitter_ms()
+                                    );
+                                    process::exit(0);
+                                }
+                            }
+                        }
+                    }
+                    // silence while we wait for the next frame to be due
+                    *out = pending.pop_front().unwrap_or(0);
+                }
+            }
+        });
+    });
+
+    putter_thread.join().expect("udp receiver thread panicked");
+    getter_thread.join().expect("rtp in-stream thread panicked");
+    player_thread.join().expect("rtp player thread panicked");
+}
@@ -1,6 +1,5 @@
 #![feature(generators, generator_trait)]
 
-use byteorder::{ByteOrder, NetworkEndian};
 use cpal::{
     self,
     traits::{EventLoopTrait, HostTrait},
@@ -10,13 +9,13 @@ use crossbeam::{
     utils::Backoff,
 };
 use jitters::{
-    rtp::{RtpHeader, RtpInStream, JITTERS_MAX_PACKET_SIZE, JITTERS_SAMPLE_RATE},
-    util::samples_to_ms,
+    codec::{codec_for, Codec},
+    payload::PayloadFormat,
+    rtp::{RtpInStream, RTP_MAX_DATAGRAM_SIZE},
 };
 use std::{
     env, mem,
-    mem::size_of,
-    net::UdpSocket,
+    net::{SocketAddr, UdpSocket},
     ops::{Generator, GeneratorState},
     pin::Pin,
     process,
@@ -26,21 +25,45 @@ use std::{
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() - 1 != 1 {
-        eprintln!("usage: {} listenhostport", args[0]);
+    if args.len() - 1 != 1 && args.len() - 1 != 3 {
+        eprintln!(
+            "usage: {} listenhostport [payloadtype encoding/clockrate/channels]",
+            args[0]
+        );
         process::exit(-1);
     }
     let listenhostport = String::from(&args[1]);
+
+    // dynamic payload types have to be spelled out, like SDP's a=rtpmap:96 L16/48000/2
+    let payload_format = if args.len() - 1 == 3 {
+        match PayloadFormat::from_rtpmap(args[2].parse().unwrap(), &args[3]) {
+            Ok(payload_format) => Some(payload_format),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(-1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // RTCP on the next port up, by convention
+    let mut rtcp_listen_addr: SocketAddr = listenhostport.parse().unwrap();
+    rtcp_listen_addr.set_port(rtcp_listen_addr.port() + 1);
     let packet_queue = Arc::new(ArrayQueue::<Vec<u8>>::new(1000));
 
     let put_packet_queue = packet_queue.clone(); // "put" ref to the packet_queue
     let putter_thread = thread::spawn(move || {
-        let mut buf = [0u8; JITTERS_MAX_PACKET_SIZE + size_of::<RtpHeader>()];
+        let mut buf = vec![0u8; RTP_MAX_DATAGRAM_SIZE];
         let udp_sock = UdpSocket::bind(listenhostport).unwrap();
         loop {
             let (amt, src) = udp_sock.recv_from(&mut buf).unwrap();
             println!("Received {:#?} bytes from {}:{}", amt, src.ip(), src.port());
-            match put_packet_queue.push(buf.to_vec()) {
+            if amt == buf.len() {
+                println!("Dropping it, it may have been cut off");
+                continue;
+            }
+            match put_packet_queue.push(buf[..amt].to_vec()) {
                 Ok(()) => {}
                 Err(PushError(_)) => panic!("couldn't push next packet onto the queue"),
             }
@@ -49,6 +72,21 @@ fn main() {
 
     let rtp_stream: Arc<Mutex<Option<RtpInStream>>> = Arc::new(Mutex::new(None));
 
+    let rtcp_rtp_stream = rtp_stream.clone(); // "rtcp" ref to the RtpInStream
+    let _rtcp_thread = thread::spawn(move || {
+        let mut buf = [0u8; 1500];
+        let udp_sock = UdpSocket::bind(rtcp_listen_addr).unwrap();
+        loop {
+            let (amt, src) = udp_sock.recv_from(&mut buf).unwrap();
+            println!("Received RTCP from {}:{}", src.ip(), src.port());
+            if let Some(ref mut rtp_stream_) = *rtcp_rtp_stream.lock().unwrap() {
+                if let Err(err) = rtp_stream_.receive_rtcp(&buf[..amt]) {
+                    eprintln!("Dropping RTCP packet: {}", err);
+                }
+            }
+        }
+    });
+
     let get_packet_queue = packet_queue.clone(); // "get" ref to the packet_queue
     let put_rtp_stream = rtp_stream.clone(); // "put" ref to the RtpInStream
     let getter_thread = thread::spawn(move || {
@@ -59,10 +97,22 @@ fn main() {
                 match get_packet_queue.pop() {
                     Ok(packet) => {
                         if let Some(ref mut rtp_stream_) = *mutex_guard {
-                            rtp_stream_.next_packet(&packet);
+                            if let Err(err) = rtp_stream_.next_packet(&packet) {
+                                eprintln!("Dropping packet: {}", err);
+                            }
                         } else {
-                            //rtp_stream = ;
-                            mem::replace(&mut *mutex_guard, Some(RtpInStream::new(&packet)));
+                            let new_stream = match payload_format {
+                                Some(ref payload_format) => {
+                                    RtpInStream::with_format(&packet, payload_format.clone())
+                                }
+                                None => RtpInStream::new(&packet),
+                            };
+                            match new_stream {
+                                Ok(rtp_stream_) => {
+                                    mem::replace(&mut *mutex_guard, Some(rtp_stream_));
+                                }
+                                Err(err) => eprintln!("Dropping packet: {}", err),
+                            }
                         }
                         continue 'outer;
                     }
@@ -85,6 +135,10 @@ fn main() {
                 if rtp_stream_.ended() {
                     // play
                     println!("Stream ended - playing audio...");
+                    println!(
+                        "Interarrival jitter: {:.3}ms",
+                        rtp_stream_.interarrival_jitter_ms()
+                    );
 
                     let host = cpal::default_host();
                     let event_loop = host.event_loop();
@@ -94,7 +148,7 @@ fn main() {
 
                     let format = cpal::Format {
                         channels: rtp_stream_.channels as cpal::ChannelCount,
-                        sample_rate: cpal::SampleRate(JITTERS_SAMPLE_RATE),
+                        sample_rate: cpal::SampleRate(rtp_stream_.format().clock_rate()),
                         data_type: cpal::SampleFormat::I16,
                     };
 
@@ -104,29 +158,20 @@ fn main() {
                         .play_stream(stream_id.clone())
                         .expect("couldn't play_stream on event_loop");
 
+                    let mut codec = codec_for(rtp_stream_.format().clone()).unwrap();
+                    let channels = rtp_stream_.channels as usize;
+
                     let mut next_value_generator = || {
                         for audio_info in rtp_stream_.audio_slices.iter() {
                             let (audio_slice, seq, timestamp) = audio_info;
                             println!(
                                 "Yielding audio slice for sequence {:#?}, timestamp {:#?}ms",
                                 seq,
-                                samples_to_ms(*timestamp as usize, rtp_stream_.channels)
+                                rtp_stream_.format().samples_to_ms(*timestamp as usize)
                             );
-                            for audio_slice_slice in
-                                audio_slice.chunks(2 * rtp_stream_.channels as usize)
-                            {
-                                let mut ret = vec![0i16; rtp_stream_.channels as usize];
-                                match rtp_stream_.channels {
-                                    1 => {
-                                        ret[0] = NetworkEndian::read_i16(&audio_slice_slice);
-                                    }
-                                    2 => {
-                                        ret[0] = NetworkEndian::read_i16(&audio_slice_slice);
-                                        ret[1] = NetworkEndian::read_i16(&audio_slice_slice[2..]);
-                                    }
-                                    _ => panic!("come on bruh"),
-                                }
-                                yield ret;
+                            let pcm = codec.decode(audio_slice);
+                            for frame in pcm.chunks(channels) {
+                                yield frame.to_vec();
                             }
                         }
                         return;
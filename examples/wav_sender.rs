@@ -1,16 +1,27 @@
-use byteorder::{ByteOrder, NetworkEndian};
 use hound::WavReader;
 use jitters::{
-    rtp::{RtpOutStream, JITTERS_MAX_PACKET_SIZE, JITTERS_SAMPLE_RATE},
-    util::samples_to_ms,
+    codec::L24,
+    pacer::RtpPacer,
+    payload::PayloadFormat,
+    rtcp_interval::{RtcpAction, RtcpScheduler},
+    rtp::{RtpOutStream, JITTERS_SAMPLE_RATE},
 };
-use sample::{signal, Sample, Signal};
-use std::{env, net::UdpSocket, process};
+use std::{
+    env,
+    net::{SocketAddr, UdpSocket},
+    process,
+    time::{Instant, SystemTime},
+};
+
+const G711_PTIME_MS: u32 = 20; // what SIP gear expects
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() - 1 != 3 {
-        eprintln!("usage: {} bindhostport sendhostport file.wav", args[0]);
+    if args.len() - 1 != 3 && args.len() - 1 != 4 {
+        eprintln!(
+            "usage: {} bindhostport sendhostport file.wav [pcmu|pcma]",
+            args[0]
+        );
         process::exit(-1);
     }
 
@@ -20,6 +31,10 @@ fn main() {
 
     let udp_sock = UdpSocket::bind(bindhostport).unwrap();
 
+    // RTCP goes to the next port up, by convention
+    let mut rtcp_addr: SocketAddr = sendhostport.parse().unwrap();
+    rtcp_addr.set_port(rtcp_addr.port() + 1);
+
     let reader = WavReader::open(wavpath).unwrap();
     let file_spec = reader.spec();
 
@@ -28,80 +43,104 @@ fn main() {
         process::exit(-1);
     }
 
-    if file_spec.sample_rate != JITTERS_SAMPLE_RATE {
-        eprintln!(
-            "Input clip must have sampling rate of {:#?}",
-            JITTERS_SAMPLE_RATE
+    // G.711 has static payload types, but it's 8000Hz mono only
+    let g711_format = match args.get(4).map(String::as_str) {
+        Some("pcmu") => Some(PayloadFormat::from_static(0).unwrap()),
+        Some("pcma") => Some(PayloadFormat::from_static(8).unwrap()),
+        Some(codec) => {
+            eprintln!("Unknown codec {}", codec);
+            process::exit(-1);
+        }
+        None => None,
+    };
+
+    // 44100 has static payload types, anything else goes out as L16 under dynamic PT 96
+    // and 24-bit wavs as L24 under dynamic PT 97, in packets as big as fit
+    let rtp_stream = if let Some(format) = g711_format {
+        if file_spec.sample_rate != 8000 || file_spec.channels != 1 {
+            eprintln!("G.711 needs an 8000Hz mono wav");
+            process::exit(-1);
+        }
+        let mut rtp_stream = RtpOutStream::with_format(format).unwrap();
+        rtp_stream.set_ptime(G711_PTIME_MS).unwrap();
+        rtp_stream
+    } else if file_spec.bits_per_sample == 24 {
+        let format =
+            PayloadFormat::new(97, "L24", file_spec.sample_rate, file_spec.channels).unwrap();
+        println!(
+            "Sending a=rtpmap:{} {}, tell the receiver",
+            format.payload_type(),
+            format.rtpmap()
         );
-        process::exit(-1);
-    }
+        RtpOutStream::with_format(format).unwrap()
+    } else if file_spec.sample_rate == JITTERS_SAMPLE_RATE {
+        RtpOutStream::new(file_spec.channels)
+    } else {
+        let format = PayloadFormat::l16(96, file_spec.sample_rate, file_spec.channels).unwrap();
+        println!(
+            "Sending a=rtpmap:{} {}, tell the receiver",
+            format.payload_type(),
+            format.rtpmap()
+        );
+        RtpOutStream::with_format(format).unwrap()
+    };
 
-    let mut rtp_stream = RtpOutStream::new(file_spec.channels);
-    let samples = reader
-        .into_samples()
-        .filter_map(Result::ok)
-        .map(i16::to_sample::<f64>);
-    let mut buf = vec![0u8; JITTERS_MAX_PACKET_SIZE];
+    let frame_size = rtp_stream.format().frame_size().unwrap();
+    let session_bw = f64::from(file_spec.sample_rate) * frame_size as f64;
+    let mut rtcp_scheduler = RtcpScheduler::new(session_bw, Instant::now());
+    rtcp_scheduler.set_members(2, 1); // us and the receiver
     let mut time_in_ms = 0.0f64;
-    let time_incr = samples_to_ms(JITTERS_MAX_PACKET_SIZE, file_spec.channels);
-
-    match file_spec.channels {
-        1 => {
-            let signal = signal::from_interleaved_samples_iter::<_, [f64; 1]>(samples);
-            let chunkable_signal = signal.until_exhausted().collect::<Vec<_>>();
-
-            let last_i = chunkable_signal.len() / (JITTERS_MAX_PACKET_SIZE / 2);
-
-            for (i, frames) in chunkable_signal
-                .chunks(JITTERS_MAX_PACKET_SIZE / 2)
-                .enumerate()
-            {
-                for (j, frame) in frames.iter().enumerate() {
-                    let single_sample = frame[0].to_sample::<i16>();
-                    NetworkEndian::write_i16(&mut buf[2 * j..], single_sample);
-                }
-                let next_packet = if i == last_i {
-                    rtp_stream.last_packet(&buf)
-                } else {
-                    rtp_stream.next_packet(&buf)
-                };
-                println!(
-                    "Sent samples at timestamp {:#?}ms with RTP over UDP to {:#?}",
-                    time_in_ms, sendhostport,
-                );
-                udp_sock.send_to(&next_packet, sendhostport).unwrap();
-                time_in_ms += time_incr;
-            }
+    let time_incr = rtp_stream.ptime_ms();
+
+    // the pacer sends them out in real time, like a live source, instead of all at once
+    let mut pacer = RtpPacer::new(rtp_stream);
+
+    if file_spec.bits_per_sample == 24 {
+        // straight through, all 24 bits of the wav end up in the payload
+        let samples = reader
+            .into_samples::<i32>()
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
+        let packet_len = pacer.rtp_stream().samples_per_packet() * file_spec.channels as usize;
+        for pcm in samples.chunks(packet_len) {
+            let packet = pacer.rtp_stream().next_packet(&L24::pack(pcm));
+            pacer.push_packet(packet);
         }
-        2 => {
-            let signal = signal::from_interleaved_samples_iter::<_, [f64; 2]>(samples);
-            let chunkable_signal = signal.until_exhausted().collect::<Vec<_>>();
-
-            let last_i = chunkable_signal.len() / (JITTERS_MAX_PACKET_SIZE / 4);
-
-            for (i, frames) in chunkable_signal
-                .chunks(JITTERS_MAX_PACKET_SIZE / 4)
-                .enumerate()
-            {
-                for (j, frame) in frames.iter().enumerate() {
-                    let chan1_sample = frame[0].to_sample::<i16>();
-                    let chan2_sample = frame[1].to_sample::<i16>();
-                    NetworkEndian::write_i16(&mut buf[4 * j..], chan1_sample);
-                    NetworkEndian::write_i16(&mut buf[4 * j + 2..], chan2_sample); //some ratty manual interleaving
-                }
-                let next_packet = if i == last_i {
-                    rtp_stream.last_packet(&buf)
-                } else {
-                    rtp_stream.next_packet(&buf)
-                };
-                println!(
-                    "Sent samples at timestamp {:#?}ms with RTP over UDP to 127.0.0.1:1337",
-                    time_in_ms
-                );
-                udp_sock.send_to(&next_packet, "127.0.0.1:1337").unwrap();
-                time_in_ms += time_incr;
-            }
+    } else {
+        // interleaved already, the out-stream chops it up and the codec takes care of L16 or G.711
+        let pcm = reader
+            .into_samples::<i16>()
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
+        pacer.push(&pcm);
+        pacer.flush();
+    }
+
+    while let Some(next_packet) = pacer.wait() {
+        println!(
+            "Sent samples at timestamp {:#?}ms with RTP over UDP to {:#?}",
+            time_in_ms, sendhostport,
+        );
+        udp_sock.send_to(&next_packet, sendhostport).unwrap();
+        time_in_ms += time_incr;
+        rtcp_scheduler.data_sent();
+
+        if rtcp_scheduler.poll(Instant::now()) == RtcpAction::SendReport {
+            println!("Sending RTCP SR to {}", rtcp_addr);
+            let sr = pacer.rtp_stream().sender_report(SystemTime::now());
+            udp_sock.send_to(&sr, rtcp_addr).unwrap();
+            rtcp_scheduler.packet_sent(sr.len(), Instant::now());
         }
-        _ => panic!("nah"),
+    }
+
+    // instead of setting the marker bit on the last packet
+    println!("End... sending RTCP BYE to {}", rtcp_addr);
+    let bye = pacer
+        .rtp_stream()
+        .bye(Some("end of file"), SystemTime::now());
+
+    // with just the two of us there's no need to back off before saying goodbye
+    if rtcp_scheduler.leave(bye.len(), Instant::now()) {
+        udp_sock.send_to(&bye, rtcp_addr).unwrap();
     }
 }
@@ -0,0 +1,292 @@
+use crate::{error::Error, g711::G711, payload::PayloadFormat};
+use byteorder::{ByteOrder, NetworkEndian};
+
+/* a codec turns interleaved i16 PCM into RTP payloads and back
+ * RtpOutStream and RtpJitterInStream are generic over it, so bring your own
+ */
+pub trait Codec {
+    // the payload type, clock rate and channels the payloads go out with
+    fn format(&self) -> &PayloadFormat;
+
+    fn encode(&mut self, pcm: &[i16]) -> Vec<u8>;
+
+    fn decode(&mut self, payload: &[u8]) -> Vec<i16>;
+
+    fn clock_rate(&self) -> u32 {
+        self.format().clock_rate()
+    }
+
+    // samples (per channel) in one codec frame, a payload is made of whole frames
+    // sample based codecs like L16 and G.711 have frames of 1 sample
+    fn frame_samples(&self) -> usize {
+        1
+    }
+
+    fn frame_duration_ms(&self) -> f64 {
+        1000.0 * self.frame_samples() as f64 / f64::from(self.clock_rate())
+    }
+
+    // sample frames (one sample per channel) in a payload, what the RTP timestamp advances by
+    // only the codec can tell for encodings that aren't sample based, so there's no default
+    // see: https://tools.ietf.org/html/rfc3550#section-5.1
+    fn payload_samples(&self, payload: &[u8]) -> usize;
+
+    // packet loss concealment - a payload to play in place of a lost one, given the last one we played
+    // the default is the crudest waveform substitution, the same packet again
+    fn conceal(&mut self, last_payload: &[u8]) -> Vec<u8> {
+        last_payload.to_vec()
+    }
+}
+
+// what the streams use when the codec is only known at runtime, e.g. from the payload type
+pub type BoxedCodec = Box<dyn Codec + Send + Sync>;
+
+impl Codec for BoxedCodec {
+    fn format(&self) -> &PayloadFormat {
+        (**self).format()
+    }
+
+    fn encode(&mut self, pcm: &[i16]) -> Vec<u8> {
+        (**self).encode(pcm)
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> Vec<i16> {
+        (**self).decode(payload)
+    }
+
+    fn clock_rate(&self) -> u32 {
+        (**self).clock_rate()
+    }
+
+    fn frame_samples(&self) -> usize {
+        (**self).frame_samples()
+    }
+
+    fn frame_duration_ms(&self) -> f64 {
+        (**self).frame_duration_ms()
+    }
+
+    fn payload_samples(&self, payload: &[u8]) -> usize {
+        (**self).payload_samples(payload)
+    }
+
+    fn conceal(&mut self, last_payload: &[u8]) -> Vec<u8> {
+        (**self).conceal(last_payload)
+    }
+}
+
+// the codecs we ship, by encoding name
+pub fn codec_for(format: PayloadFormat) -> Result<BoxedCodec, Error> {
+    match format.encoding_name() {
+        "L16" => Ok(Box::new(L16::new(format)?)),
+        "L24" => Ok(Box::new(L24::new(format)?)),
+        "L8" => Ok(Box::new(L8::new(format)?)),
+        "PCMU" | "PCMA" => Ok(Box::new(G711::new(format)?)),
+        _ => Err(Error::UnsupportedPayloadFormat(format.rtpmap())),
+    }
+}
+
+// 16-bit signed big-endian linear PCM, channels interleaved
+// see: https://tools.ietf.org/html/rfc3551#section-4.5.11
+pub struct L16 {
+    format: PayloadFormat,
+}
+
+impl L16 {
+    pub fn new(format: PayloadFormat) -> Result<Self, Error> {
+        if format.encoding_name() != "L16" {
+            return Err(Error::UnsupportedPayloadFormat(format.rtpmap()));
+        }
+        Ok(L16 { format })
+    }
+}
+
+impl Codec for L16 {
+    fn format(&self) -> &PayloadFormat {
+        &self.format
+    }
+
+    fn encode(&mut self, pcm: &[i16]) -> Vec<u8> {
+        let mut payload = vec![0u8; 2 * pcm.len()];
+        NetworkEndian::write_i16_into(pcm, &mut payload);
+        payload
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> Vec<i16> {
+        let mut pcm = vec![0i16; payload.len() / 2];
+        NetworkEndian::read_i16_into(&payload[..2 * pcm.len()], &mut pcm);
+        pcm
+    }
+
+    fn payload_samples(&self, payload: &[u8]) -> usize {
+        payload.len() / (2 * self.format.channels() as usize)
+    }
+}
+
+// 24-bit signed big-endian linear PCM, channels interleaved
+// the Codec side is i16 like everything else, i.e. 16-in-24 (shifted up 8 bits, the low byte 0 on the way out
+// and dropped on the way in), only pack/unpack keep all 24 bits
+// see: https://tools.ietf.org/html/rfc3190#section-4
+pub struct L24 {
+    format: PayloadFormat,
+}
+
+impl L24 {
+    pub fn new(format: PayloadFormat) -> Result<Self, Error> {
+        if format.encoding_name() != "L24" {
+            return Err(Error::UnsupportedPayloadFormat(format.rtpmap()));
+        }
+        Ok(L24 { format })
+    }
+
+    // samples in the low 24 bits of an i32, anything above is clipped
+    pub fn pack(pcm: &[i32]) -> Vec<u8> {
+        let mut payload = vec![0u8; 3 * pcm.len()];
+        for (sample, bytes) in pcm.iter().zip(payload.chunks_mut(3)) {
+            NetworkEndian::write_i24(bytes, (*sample).clamp(-0x80_0000, 0x7f_ffff));
+        }
+        payload
+    }
+
+    pub fn unpack(payload: &[u8]) -> Vec<i32> {
+        payload
+            .chunks_exact(3)
+            .map(NetworkEndian::read_i24)
+            .collect()
+    }
+}
+
+impl Codec for L24 {
+    fn format(&self) -> &PayloadFormat {
+        &self.format
+    }
+
+    fn encode(&mut self, pcm: &[i16]) -> Vec<u8> {
+        let pcm = pcm
+            .iter()
+            .map(|sample| i32::from(*sample) << 8)
+            .collect::<Vec<_>>();
+        L24::pack(&pcm)
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> Vec<i16> {
+        L24::unpack(payload)
+            .into_iter()
+            .map(|sample| (sample >> 8) as i16)
+            .collect()
+    }
+
+    fn payload_samples(&self, payload: &[u8]) -> usize {
+        payload.len() / (3 * self.format.channels() as usize)
+    }
+}
+
+// 8-bit linear PCM, offset by 128 so the most negative sample is 0
+// see: https://tools.ietf.org/html/rfc3551#section-4.5.10
+pub struct L8 {
+    format: PayloadFormat,
+}
+
+impl L8 {
+    pub fn new(format: PayloadFormat) -> Result<Self, Error> {
+        if format.encoding_name() != "L8" {
+            return Err(Error::UnsupportedPayloadFormat(format.rtpmap()));
+        }
+        Ok(L8 { format })
+    }
+}
+
+impl Codec for L8 {
+    fn format(&self) -> &PayloadFormat {
+        &self.format
+    }
+
+    fn encode(&mut self, pcm: &[i16]) -> Vec<u8> {
+        pcm.iter()
+            .map(|sample| ((sample >> 8) + 128) as u8)
+            .collect()
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> Vec<i16> {
+        payload
+            .iter()
+            .map(|byte| (i16::from(*byte) - 128) << 8)
+            .collect()
+    }
+
+    fn payload_samples(&self, payload: &[u8]) -> usize {
+        payload.len() / self.format.channels() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_l16() {
+        let mut codec = L16::new(PayloadFormat::l16(96, 48000, 2).unwrap()).unwrap();
+        assert_eq!(codec.clock_rate(), 48000);
+        assert_eq!(codec.frame_samples(), 1);
+
+        let pcm = vec![0i16, 1, -1, i16::MAX, i16::MIN, 0x1234];
+        let payload = codec.encode(&pcm);
+        assert_eq!(payload[..4], [0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(payload[10..], [0x12, 0x34]);
+        assert_eq!(codec.decode(&payload), pcm);
+
+        // half a sample is dropped
+        assert_eq!(codec.decode(&payload[..3]), vec![0i16]);
+
+        assert!(L16::new(PayloadFormat::from_static(0).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_l24() {
+        let mut codec = codec_for(PayloadFormat::from_rtpmap(97, "L24/48000/2").unwrap()).unwrap();
+
+        let pcm24 = vec![0i32, 1, -1, 0x7f_ffff, -0x80_0000, 0x12_3456];
+        let payload = L24::pack(&pcm24);
+        assert_eq!(payload.len(), 18);
+        assert_eq!(payload[3..9], [0x00, 0x00, 0x01, 0xff, 0xff, 0xff]);
+        assert_eq!(payload[15..], [0x12, 0x34, 0x56]);
+        assert_eq!(L24::unpack(&payload), pcm24);
+        assert_eq!(L24::pack(&[0x100_0000]), vec![0x7f, 0xff, 0xff]);
+
+        // 6 samples of stereo is 3 sample frames
+        assert_eq!(codec.payload_samples(&payload), 3);
+
+        let pcm = vec![0i16, -1, i16::MAX, i16::MIN];
+        let payload = codec.encode(&pcm);
+        assert_eq!(payload[..6], [0x00, 0x00, 0x00, 0xff, 0xff, 0x00]);
+        assert_eq!(codec.decode(&payload), pcm);
+    }
+
+    #[test]
+    fn test_l8() {
+        let mut codec = codec_for(PayloadFormat::from_rtpmap(98, "L8/8000").unwrap()).unwrap();
+
+        let pcm = vec![0i16, i16::MIN, i16::MAX, 0x1234, -0x1234];
+        let payload = codec.encode(&pcm);
+        assert_eq!(payload, vec![0x80, 0x00, 0xff, 0x92, 0x6d]);
+        assert_eq!(codec.payload_samples(&payload), 5);
+        assert_eq!(
+            codec.decode(&payload),
+            vec![0, i16::MIN, 0x7f00, 0x1200, -0x1300]
+        );
+    }
+
+    #[test]
+    fn test_boxed() {
+        let mut codec = codec_for(PayloadFormat::from_static(8).unwrap()).unwrap();
+        assert_eq!(codec.format().encoding_name(), "PCMA");
+        assert_eq!(codec.frame_duration_ms(), 0.125);
+        assert_eq!(codec.encode(&[0i16, 0i16]), vec![0xd5, 0xd5]);
+        assert_eq!(codec.conceal(&[0xd5, 0xd5]), vec![0xd5, 0xd5]);
+
+        let mut codec = codec_for(PayloadFormat::from_static(11).unwrap()).unwrap();
+        assert_eq!(codec.decode(&[0x12, 0x34]), vec![0x1234]);
+
+        assert!(codec_for(PayloadFormat::from_rtpmap(111, "opus/48000/2").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use std::{error, fmt};
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    TruncatedPacket(usize),
+    BadVersion(u8),
+    BadPadding(u8),
+    MalformedExtension,
+    UnsupportedPayloadType(u8),
+    UnsupportedPayloadFormat(String),
+    PayloadTypeMismatch { expected: u8, got: u8 },
+    SsrcMismatch { expected: u32, got: u32 },
+    UnsupportedRtcpPacketType(u8),
+    BadCompoundPacket,
+    BadPtime(u32),
+    BadMtu(usize),
+    BadDelayBounds { min_ms: f64, max_ms: f64 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TruncatedPacket(len) => write!(f, "packet too short: {} bytes", len),
+            Error::BadVersion(version) => write!(f, "unsupported RTP version {}", version),
+            Error::BadPadding(padding) => write!(f, "invalid padding count {}", padding),
+            Error::MalformedExtension => write!(f, "malformed RTP header extension"),
+            Error::UnsupportedPayloadType(pt) => write!(f, "unsupported payload type {}", pt),
+            Error::UnsupportedPayloadFormat(rtpmap) => {
+                write!(f, "unsupported payload format {}", rtpmap)
+            }
+            Error::PayloadTypeMismatch { expected, got } => write!(
+                f,
+                "payload type changed mid-stream: expected {}, got {}",
+                expected, got
+            ),
+            Error::SsrcMismatch { expected, got } => write!(
+                f,
+                "packet from a different rtp stream: expected ssrc {:#x}, got {:#x}",
+                expected, got
+            ),
+            Error::UnsupportedRtcpPacketType(pt) => {
+                write!(f, "unsupported RTCP packet type {}", pt)
+            }
+            Error::BadCompoundPacket => write!(f, "invalid compound RTCP packet"),
+            Error::BadPtime(ptime) => write!(
+                f,
+                "ptime {}ms isn't a whole number of frames or doesn't fit in a packet",
+                ptime
+            ),
+            Error::BadMtu(mtu) => write!(f, "mtu {} is too small for a single frame", mtu),
+            Error::BadDelayBounds { min_ms, max_ms } => write!(
+                f,
+                "min delay {}ms is larger than max delay {}ms",
+                min_ms, max_ms
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,225 @@
+use crate::{codec::Codec, error::Error, payload::PayloadFormat};
+
+/* G.711 mu-law (PCMU, PT 0) and A-law (PCMA, PT 8), 8000Hz mono, one byte per sample
+ * this is the usual segment search from the Sun reference implementation
+ * see: https://www.itu.int/rec/T-REC-G.711
+ *      https://tools.ietf.org/html/rfc3551#section-4.5.14
+ */
+const SIGN_BIT: u8 = 0x80;
+const QUANT_MASK: u8 = 0x0f;
+const SEG_SHIFT: u8 = 4;
+const SEG_MASK: u8 = 0x70;
+
+const ULAW_BIAS: i32 = 0x84;
+const ULAW_CLIP: i32 = 8159;
+
+const SEG_UEND: [i32; 8] = [0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff, 0x1fff];
+const SEG_AEND: [i32; 8] = [0x1f, 0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff];
+
+fn segment(val: i32, table: &[i32; 8]) -> usize {
+    table.iter().position(|&end| val <= end).unwrap_or(8)
+}
+
+pub fn linear_to_ulaw(sample: i16) -> u8 {
+    let mut val = i32::from(sample) >> 2; // 14 bits
+
+    let mask = if val < 0 {
+        val = -val;
+        0x7f
+    } else {
+        0xff
+    };
+    val = val.min(ULAW_CLIP) + (ULAW_BIAS >> 2);
+
+    let seg = segment(val, &SEG_UEND);
+    if seg >= 8 {
+        return 0x7f ^ mask; // out of range, the maximum value
+    }
+    let uval = ((seg as u8) << SEG_SHIFT) | ((val >> (seg + 1)) as u8 & QUANT_MASK);
+    uval ^ mask
+}
+
+pub fn ulaw_to_linear(ulaw: u8) -> i16 {
+    let ulaw = !ulaw;
+
+    let mut t = (i32::from(ulaw & QUANT_MASK) << 3) + ULAW_BIAS;
+    t <<= (ulaw & SEG_MASK) >> SEG_SHIFT;
+
+    if ulaw & SIGN_BIT != 0 {
+        (ULAW_BIAS - t) as i16
+    } else {
+        (t - ULAW_BIAS) as i16
+    }
+}
+
+pub fn linear_to_alaw(sample: i16) -> u8 {
+    let mut val = i32::from(sample) >> 3; // 13 bits
+
+    let mask = if val >= 0 {
+        0xd5
+    } else {
+        val = -val - 1;
+        0x55
+    };
+
+    let seg = segment(val, &SEG_AEND);
+    if seg >= 8 {
+        return 0x7f ^ mask;
+    }
+    let shift = if seg < 2 { 1 } else { seg };
+    let aval = ((seg as u8) << SEG_SHIFT) | ((val >> shift) as u8 & QUANT_MASK);
+    aval ^ mask
+}
+
+pub fn alaw_to_linear(alaw: u8) -> i16 {
+    let alaw = alaw ^ 0x55;
+
+    let mut t = i32::from(alaw & QUANT_MASK) << 4;
+    match (alaw & SEG_MASK) >> SEG_SHIFT {
+        0 => t += 8,
+        1 => t += 0x108,
+        seg => {
+            t += 0x108;
+            t <<= seg - 1;
+        }
+    }
+
+    if alaw & SIGN_BIT != 0 {
+        t as i16
+    } else {
+        -t as i16
+    }
+}
+
+pub fn encode_ulaw(samples: &[i16]) -> Vec<u8> {
+    samples
+        .iter()
+        .map(|&sample| linear_to_ulaw(sample))
+        .collect()
+}
+
+pub fn decode_ulaw(payload: &[u8]) -> Vec<i16> {
+    payload.iter().map(|&ulaw| ulaw_to_linear(ulaw)).collect()
+}
+
+pub fn encode_alaw(samples: &[i16]) -> Vec<u8> {
+    samples
+        .iter()
+        .map(|&sample| linear_to_alaw(sample))
+        .collect()
+}
+
+pub fn decode_alaw(payload: &[u8]) -> Vec<i16> {
+    payload.iter().map(|&alaw| alaw_to_linear(alaw)).collect()
+}
+
+pub struct G711 {
+    format: PayloadFormat,
+}
+
+impl G711 {
+    // PCMU or PCMA, which RFC 3551 only defines at 8000 Hz mono
+    pub fn new(format: PayloadFormat) -> Result<Self, Error> {
+        match (
+            format.encoding_name(),
+            format.clock_rate(),
+            format.channels(),
+        ) {
+            ("PCMU", 8000, 1) | ("PCMA", 8000, 1) => Ok(G711 { format }),
+            _ => Err(Error::UnsupportedPayloadFormat(format.rtpmap())),
+        }
+    }
+}
+
+impl Codec for G711 {
+    fn format(&self) -> &PayloadFormat {
+        &self.format
+    }
+
+    fn encode(&mut self, pcm: &[i16]) -> Vec<u8> {
+        match self.format.encoding_name() {
+            "PCMU" => encode_ulaw(pcm),
+            _ => encode_alaw(pcm),
+        }
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> Vec<i16> {
+        match self.format.encoding_name() {
+            "PCMU" => decode_ulaw(payload),
+            _ => decode_alaw(payload),
+        }
+    }
+
+    // one byte per sample, always mono
+    fn payload_samples(&self, payload: &[u8]) -> usize {
+        payload.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ulaw() {
+        assert_eq!(linear_to_ulaw(0), 0xff);
+        assert_eq!(ulaw_to_linear(0xff), 0);
+        assert_eq!(ulaw_to_linear(0x80), 32124);
+        assert_eq!(ulaw_to_linear(0x00), -32124);
+        assert_eq!(linear_to_ulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_ulaw(i16::MIN), 0x00);
+
+        // every code survives a round trip, except 0x7f which is -0
+        for ulaw in (0..=255u8).filter(|&ulaw| ulaw != 0x7f) {
+            assert_eq!(linear_to_ulaw(ulaw_to_linear(ulaw)), ulaw);
+        }
+
+        // the quantization error grows with the segment, but stays within a step
+        for sample in (-32124..=32124i32).step_by(7) {
+            let decoded = i32::from(ulaw_to_linear(linear_to_ulaw(sample as i16)));
+            let seg = segment((sample >> 2).abs() + (ULAW_BIAS >> 2), &SEG_UEND);
+            assert!((decoded - sample).abs() <= 8 << seg);
+        }
+    }
+
+    #[test]
+    fn test_alaw() {
+        assert_eq!(linear_to_alaw(0), 0xd5);
+        assert_eq!(alaw_to_linear(0xd5), 8);
+        assert_eq!(alaw_to_linear(0x55), -8);
+        assert_eq!(alaw_to_linear(0xaa), 32256);
+        assert_eq!(alaw_to_linear(0x2a), -32256);
+        assert_eq!(linear_to_alaw(i16::MAX), 0xaa);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2a);
+
+        for alaw in 0..=255u8 {
+            assert_eq!(linear_to_alaw(alaw_to_linear(alaw)), alaw);
+        }
+
+        for sample in (-32256..=32256i32).step_by(7) {
+            let decoded = i32::from(alaw_to_linear(linear_to_alaw(sample as i16)));
+            assert!((decoded - sample).abs() <= 16 << segment(sample.abs() >> 3, &SEG_AEND));
+        }
+    }
+
+    #[test]
+    fn test_slices() {
+        let samples = vec![0i16, 1000, -1000, 32000, -32000];
+        assert_eq!(decode_ulaw(&encode_ulaw(&samples)).len(), samples.len());
+        assert_eq!(encode_alaw(&decode_alaw(&[0xd5, 0x55])), vec![0xd5, 0x55]);
+    }
+
+    #[test]
+    fn test_formats() {
+        assert!(G711::new(PayloadFormat::from_static(0).unwrap()).is_ok());
+        assert!(G711::new(PayloadFormat::from_rtpmap(96, "PCMA/8000").unwrap()).is_ok());
+
+        for rtpmap in &["PCMU/16000", "PCMA/8000/2", "L16/8000"] {
+            let format = PayloadFormat::from_rtpmap(96, rtpmap).unwrap();
+            assert_eq!(
+                G711::new(format.clone()).err(),
+                Some(Error::UnsupportedPayloadFormat(format.rtpmap()))
+            );
+        }
+    }
+}
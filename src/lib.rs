@@ -1,3 +1,18 @@
+pub mod codec;
+mod error;
+pub mod g711;
+pub mod pacer;
+pub mod payload;
+pub mod playout;
+pub mod plc;
+pub mod rtcp;
+pub mod rtcp_interval;
 pub mod rtp;
+pub mod rtp_ext;
 pub mod rtp_jitter;
+pub mod rtp_sender;
+pub mod rtp_session;
+pub mod rtp_source;
 pub mod util;
+
+pub use crate::error::Error;
@@ -0,0 +1,221 @@
+use crate::{
+    codec::{BoxedCodec, Codec},
+    rtp::RtpOutStream,
+};
+use byteorder::{ByteOrder, NetworkEndian};
+use std::{
+    collections::VecDeque,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+pub const JITTERS_PACER_MAX_LAG_MS: u64 = 200; //further behind than this we stop catching up and start over
+
+/* sends packets at the wall-clock rate of their RTP timestamps, like a live source would
+ * each packet is due at epoch + (timestamp - epoch timestamp) / clock rate, always worked out from the
+ * same epoch so rounding and oversleeping never add up to drift
+ * a new SSRC (after a collision) starts from a random timestamp, so it gets an epoch of its own
+ * packets that are late (the thread didn't get scheduled, a slow socket) go out back to back until
+ * we've caught up, unless we're more than max_lag behind, then the epoch moves up to now instead
+ */
+pub struct RtpPacer<C: Codec = BoxedCodec> {
+    rtp_stream: RtpOutStream<C>,
+    queue: VecDeque<(u32, u32, Vec<u8>)>, // packets with their RTP timestamp and SSRC
+    epoch: Option<(Instant, u32, u32)>,   // when, RTP timestamp, SSRC
+    max_lag: Duration,
+    resyncs: u64,
+}
+
+impl<C: Codec> RtpPacer<C> {
+    pub fn new(rtp_stream: RtpOutStream<C>) -> Self {
+        RtpPacer {
+            rtp_stream,
+            queue: VecDeque::new(),
+            epoch: None,
+            max_lag: Duration::from_millis(JITTERS_PACER_MAX_LAG_MS),
+            resyncs: 0,
+        }
+    }
+
+    // the out-stream, for SRs and BYEs
+    pub fn rtp_stream(&mut self) -> &mut RtpOutStream<C> {
+        &mut self.rtp_stream
+    }
+
+    pub fn set_max_lag(&mut self, max_lag: Duration) {
+        self.max_lag = max_lag;
+    }
+
+    // interleaved PCM, packetized by the out-stream
+    pub fn push(&mut self, pcm: &[i16]) {
+        for packet in self.rtp_stream.packetize(pcm) {
+            self.push_packet(packet);
+        }
+    }
+
+    // whatever PCM the out-stream is still holding on to, e.g. at the end of a file
+    pub fn flush(&mut self) {
+        if let Some(packet) = self.rtp_stream.flush() {
+            self.push_packet(packet);
+        }
+    }
+
+    // a packet that's already been built by our out-stream, e.g. with next_packet
+    pub fn push_packet(&mut self, packet: Vec<u8>) {
+        let timestamp = NetworkEndian::read_u32(&packet[4..8]); //see the header diagram in rtp.rs
+        let ssrc = NetworkEndian::read_u32(&packet[8..12]);
+        self.queue.push_back((timestamp, ssrc, packet));
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // how many times we gave up catching up
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    // when the next packet should go out, None if nothing's queued
+    // the first packet (of each SSRC) is due whenever it's polled
+    pub fn next_due(&self) -> Option<Instant> {
+        let (timestamp, ssrc, _) = self.queue.front()?;
+        match self.epoch {
+            Some(epoch) if epoch.2 == *ssrc => Some(self.due(epoch, *timestamp)),
+            _ => Some(Instant::now()),
+        }
+    }
+
+    fn due(&self, epoch: (Instant, u32, u32), timestamp: u32) -> Instant {
+        let samples = u64::from(timestamp.wrapping_sub(epoch.1));
+        let clock_rate = u64::from(self.rtp_stream.format().clock_rate());
+        epoch.0 + Duration::from_nanos(samples * 1_000_000_000 / clock_rate)
+    }
+
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        self.poll_at(Instant::now())
+    }
+
+    // the next packet if it's due
+    pub fn poll_at(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let (timestamp, ssrc, _) = *self.queue.front()?;
+
+        let epoch = match self.epoch {
+            Some(epoch) if epoch.2 == ssrc => epoch,
+            _ => (now, timestamp, ssrc),
+        };
+        self.epoch = Some(epoch);
+
+        let due = self.due(epoch, timestamp);
+        if now < due {
+            return None;
+        }
+        if now - due > self.max_lag {
+            // too far behind to burst our way out of it, carry on in real time from here
+            self.epoch = Some((now, timestamp, ssrc));
+            self.resyncs += 1;
+        }
+
+        self.rtp_stream.mark_sent(timestamp, SystemTime::now());
+        self.queue.pop_front().map(|(_, _, packet)| packet)
+    }
+
+    // blocks until the next packet is due, None once the queue is empty
+    pub fn wait(&mut self) -> Option<Vec<u8>> {
+        let due = self.next_due()?;
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        self.poll_at(Instant::now().max(due))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pacer() -> RtpPacer {
+        // 10ms packets of 44100 mono
+        let mut rtp_stream = RtpOutStream::new(1);
+        rtp_stream.set_ptime(10).unwrap();
+        let mut pacer = RtpPacer::new(rtp_stream);
+        pacer.push(&[0i16; 441 * 5]);
+        pacer
+    }
+
+    #[test]
+    fn test_real_time() {
+        let mut pacer = pacer();
+        assert_eq!(pacer.queued(), 5);
+
+        let t0 = Instant::now();
+        assert!(pacer.poll_at(t0).is_some());
+        assert!(pacer.poll_at(t0 + Duration::from_millis(5)).is_none());
+        assert_eq!(pacer.next_due(), Some(t0 + Duration::from_millis(10)));
+
+        // being a bit late doesn't push the packets after it back
+        assert!(pacer.poll_at(t0 + Duration::from_millis(13)).is_some());
+        assert_eq!(pacer.next_due(), Some(t0 + Duration::from_millis(20)));
+        assert!(pacer.poll_at(t0 + Duration::from_millis(19)).is_none());
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let mut pacer = pacer();
+
+        let t0 = Instant::now();
+        assert!(pacer.poll_at(t0).is_some());
+
+        // 3 packets behind, they all go out now
+        let later = t0 + Duration::from_millis(35);
+        for _ in 0..3 {
+            assert!(pacer.poll_at(later).is_some());
+        }
+        assert!(pacer.poll_at(later).is_none());
+        assert_eq!(pacer.next_due(), Some(t0 + Duration::from_millis(40)));
+        assert_eq!(pacer.resyncs(), 0);
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut pacer = pacer();
+        pacer.set_max_lag(Duration::from_millis(50));
+
+        let t0 = Instant::now();
+        assert!(pacer.poll_at(t0).is_some());
+
+        // a second behind, start over from the next packet instead of bursting
+        let much_later = t0 + Duration::from_secs(1);
+        assert!(pacer.poll_at(much_later).is_some());
+        assert_eq!(pacer.resyncs(), 1);
+        assert!(pacer.poll_at(much_later).is_none());
+        assert_eq!(
+            pacer.next_due(),
+            Some(much_later + Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn test_ssrc_change() {
+        let mut pacer = pacer();
+        let t0 = Instant::now();
+        for i in 0..5 {
+            assert!(pacer.poll_at(t0 + Duration::from_millis(10 * i)).is_some());
+        }
+
+        // a collision moves us to a new SSRC and a random timestamp, the old epoch means nothing to it
+        pacer.rtp_stream().change_ssrc(&[], SystemTime::now());
+        pacer.push(&[0i16; 441 * 2]);
+
+        let t1 = t0 + Duration::from_millis(50);
+        assert!(pacer.poll_at(t1).is_some());
+        assert_eq!(pacer.next_due(), Some(t1 + Duration::from_millis(10)));
+        assert!(pacer.poll_at(t1 + Duration::from_millis(5)).is_none());
+        assert_eq!(pacer.resyncs(), 0);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::error::Error;
+use crate::util::{ms_to_samples, samples_to_ms};
+
+/* payload format descriptor, what an SDP a=rtpmap line says about a payload type
+ * e.g. a=rtpmap:96 L16/48000/2
+ * see: https://tools.ietf.org/html/rfc3551#section-6
+ *      https://tools.ietf.org/html/rfc4566#section-6
+ */
+pub const RTP_DYNAMIC_PT_MIN: u8 = 96;
+pub const RTP_DYNAMIC_PT_MAX: u8 = 127;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadFormat {
+    payload_type: u8,
+    encoding_name: String,
+    clock_rate: u32,
+    channels: u16,
+}
+
+impl PayloadFormat {
+    pub fn new(
+        payload_type: u8,
+        encoding_name: &str,
+        clock_rate: u32,
+        channels: u16,
+    ) -> Result<Self, Error> {
+        let format = PayloadFormat {
+            payload_type,
+            encoding_name: encoding_name.to_uppercase(),
+            clock_rate,
+            channels,
+        };
+
+        // the PT is only 7 bits, and 72-76 would collide with RTCP packet types
+        // see: https://tools.ietf.org/html/rfc3550#section-12.1
+        if payload_type > RTP_DYNAMIC_PT_MAX || (72..=76).contains(&payload_type) {
+            return Err(Error::UnsupportedPayloadType(payload_type));
+        }
+        // encodings we don't know the sample size of are fine, e.g. opus/48000/2,
+        // timestamps only need the clock rate and their codec counts the samples
+        if clock_rate == 0 || channels == 0 {
+            return Err(Error::UnsupportedPayloadFormat(format.rtpmap()));
+        }
+        Ok(format)
+    }
+
+    // L16 under a dynamic payload type, for anything other than 44100
+    pub fn l16(payload_type: u8, clock_rate: u32, channels: u16) -> Result<Self, Error> {
+        PayloadFormat::new(payload_type, "L16", clock_rate, channels)
+    }
+
+    // the static payload types from the RFC 3551 table that we know how to play
+    // see: https://tools.ietf.org/html/rfc3551#section-6
+    pub fn from_static(payload_type: u8) -> Result<Self, Error> {
+        match payload_type {
+            0 => PayloadFormat::new(0, "PCMU", 8000, 1),
+            8 => PayloadFormat::new(8, "PCMA", 8000, 1),
+            10 => PayloadFormat::new(10, "L16", 44100, 2),
+            11 => PayloadFormat::new(11, "L16", 44100, 1),
+            pt => Err(Error::UnsupportedPayloadType(pt)),
+        }
+    }
+
+    // the part of an a=rtpmap line after the payload type, "L16/48000/2"
+    // the channel count is optional and defaults to 1
+    pub fn from_rtpmap(payload_type: u8, rtpmap: &str) -> Result<Self, Error> {
+        let bad_rtpmap = || Error::UnsupportedPayloadFormat(rtpmap.to_string());
+
+        let mut parts = rtpmap.trim().split('/');
+        let encoding_name = parts.next().ok_or_else(bad_rtpmap)?;
+        let clock_rate = parts
+            .next()
+            .and_then(|rate| rate.parse::<u32>().ok())
+            .ok_or_else(bad_rtpmap)?;
+        let channels = match parts.next() {
+            Some(channels) => channels.parse::<u16>().map_err(|_| bad_rtpmap())?,
+            None => 1,
+        };
+        if parts.next().is_some() {
+            return Err(bad_rtpmap());
+        }
+
+        PayloadFormat::new(payload_type, encoding_name, clock_rate, channels)
+    }
+
+    pub fn rtpmap(&self) -> String {
+        format!(
+            "{}/{}/{}",
+            self.encoding_name, self.clock_rate, self.channels
+        )
+    }
+
+    pub fn payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    pub fn encoding_name(&self) -> &str {
+        &self.encoding_name
+    }
+
+    pub fn clock_rate(&self) -> u32 {
+        self.clock_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.payload_type >= RTP_DYNAMIC_PT_MIN
+    }
+
+    // bytes per sample of a single channel
+    pub fn sample_size(&self) -> Option<usize> {
+        match self.encoding_name.as_str() {
+            "L24" => Some(3),
+            "L16" => Some(2),
+            "L8" => Some(1),
+            "PCMU" | "PCMA" => Some(1), // G.711, see g711.rs
+            _ => None,
+        }
+    }
+
+    // bytes per sample frame, i.e. one sample for each channel
+    // only for the sample based encodings, see sample_size
+    pub fn frame_size(&self) -> Option<usize> {
+        self.sample_size()
+            .map(|sample_size| sample_size * self.channels as usize)
+    }
+
+    pub fn samples_to_ms(&self, samples: usize) -> f64 {
+        samples_to_ms(samples, self.clock_rate)
+    }
+
+    pub fn ms_to_samples(&self, ms: f64) -> i64 {
+        ms_to_samples(ms, self.clock_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_static_formats() {
+        let mono = PayloadFormat::from_static(11).unwrap();
+        assert_eq!(mono.rtpmap(), "L16/44100/1");
+        assert_eq!(mono.frame_size(), Some(2));
+        assert!(!mono.is_dynamic());
+
+        let stereo = PayloadFormat::from_static(10).unwrap();
+        assert_eq!(stereo.rtpmap(), "L16/44100/2");
+        assert_eq!(stereo.frame_size(), Some(4));
+
+        let pcmu = PayloadFormat::from_static(0).unwrap();
+        assert_eq!(pcmu.rtpmap(), "PCMU/8000/1");
+        assert_eq!(pcmu.frame_size(), Some(1));
+        assert_eq!(pcmu.samples_to_ms(160), 20.0);
+
+        let pcma = PayloadFormat::from_static(8).unwrap();
+        assert_eq!(pcma.rtpmap(), "PCMA/8000/1");
+
+        assert_eq!(
+            PayloadFormat::from_static(96),
+            Err(Error::UnsupportedPayloadType(96))
+        );
+    }
+
+    #[test]
+    fn test_dynamic_formats() {
+        for &rate in &[8000, 16000, 32000, 48000] {
+            let format = PayloadFormat::l16(96, rate, 2).unwrap();
+            assert!(format.is_dynamic());
+            assert_eq!(format.clock_rate(), rate);
+            assert_eq!(format.samples_to_ms(rate as usize), 1000.0);
+        }
+
+        let format = PayloadFormat::from_rtpmap(97, "L16/48000/6").unwrap();
+        assert_eq!(format.payload_type(), 97);
+        assert_eq!(format.channels(), 6);
+        assert_eq!(format.frame_size(), Some(12));
+
+        let format = PayloadFormat::from_rtpmap(97, "L24/96000/2").unwrap();
+        assert_eq!(format.frame_size(), Some(6));
+        assert_eq!(format.ms_to_samples(10.0), 960);
+
+        let format = PayloadFormat::from_rtpmap(97, "L8/8000").unwrap();
+        assert_eq!(format.frame_size(), Some(1));
+
+        let format = PayloadFormat::from_rtpmap(98, "l16/16000").unwrap();
+        assert_eq!(format.encoding_name(), "L16");
+        assert_eq!(format.channels(), 1);
+
+        assert!(PayloadFormat::from_rtpmap(96, "L16").is_err());
+        assert!(PayloadFormat::from_rtpmap(96, "L16/48000/2/1").is_err());
+        assert!(PayloadFormat::from_rtpmap(96, "L16/0/2").is_err());
+
+        // nothing we can decode, but a codec could
+        let format = PayloadFormat::from_rtpmap(111, "opus/48000/2").unwrap();
+        assert_eq!(format.encoding_name(), "OPUS");
+        assert_eq!(format.sample_size(), None);
+        assert_eq!(format.frame_size(), None);
+        assert_eq!(format.samples_to_ms(960), 20.0);
+        assert!(PayloadFormat::l16(96, 48000, 0).is_err());
+        assert!(PayloadFormat::l16(128, 48000, 1).is_err());
+        assert!(PayloadFormat::l16(72, 48000, 1).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::Error;
+use std::collections::VecDeque;
+
+pub const JITTERS_MIN_DELAY_MS: f64 = 20.0;
+pub const JITTERS_MAX_DELAY_MS: f64 = 500.0;
+
+const JITTERS_DELAY_WINDOW: usize = 256; // packets
+const JITTERS_DELAY_WARMUP: usize = 32; // don't trust the window before this many packets
+const JITTERS_DELAY_PERCENTILE: f64 = 0.95;
+
+/* the transit time of a packet is its arrival time minus its RTP timestamp
+ * (both in ms, relative to the first packet) - the absolute value is meaningless since
+ * the clocks aren't synced, but the variation over the window tells us how long
+ * the slowest packets lag behind the fastest one
+ *
+ * the target delay is a high percentile of that variation, so most packets make it in time
+ */
+pub struct PlayoutDelay {
+    transits: VecDeque<f64>,
+    initial_delay_ms: f64,
+    min_delay_ms: f64,
+    max_delay_ms: f64,
+}
+
+impl PlayoutDelay {
+    pub fn new(initial_delay_ms: f64) -> Self {
+        PlayoutDelay {
+            transits: VecDeque::with_capacity(JITTERS_DELAY_WINDOW),
+            initial_delay_ms,
+            min_delay_ms: JITTERS_MIN_DELAY_MS,
+            max_delay_ms: JITTERS_MAX_DELAY_MS,
+        }
+    }
+
+    pub fn set_bounds(&mut self, min_delay_ms: f64, max_delay_ms: f64) -> Result<(), Error> {
+        if min_delay_ms > max_delay_ms {
+            return Err(Error::BadDelayBounds {
+                min_ms: min_delay_ms,
+                max_ms: max_delay_ms,
+            });
+        }
+        self.min_delay_ms = min_delay_ms;
+        self.max_delay_ms = max_delay_ms;
+        Ok(())
+    }
+
+    pub fn update(&mut self, arrival_ms: f64, timestamp_ms: f64) {
+        if self.transits.len() == JITTERS_DELAY_WINDOW {
+            self.transits.pop_front();
+        }
+        self.transits.push_back(arrival_ms - timestamp_ms);
+    }
+
+    // transit time of the fastest packet in the window, which is what the target is relative to
+    // with nothing in the window yet that's the first packet's, 0 by definition
+    pub fn min_transit_ms(&self) -> f64 {
+        if self.transits.is_empty() {
+            return 0.0;
+        }
+        self.transits.iter().cloned().fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn target_ms(&self) -> f64 {
+        let target = if self.transits.len() < JITTERS_DELAY_WARMUP {
+            self.initial_delay_ms
+        } else {
+            let min_transit = self.min_transit_ms();
+            let mut variations = self
+                .transits
+                .iter()
+                .map(|transit| transit - min_transit)
+                .collect::<Vec<_>>();
+            variations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let idx = ((variations.len() - 1) as f64 * JITTERS_DELAY_PERCENTILE).round() as usize;
+            variations[idx]
+        };
+
+        target.max(self.min_delay_ms).min(self.max_delay_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steady_stream() {
+        let mut delay = PlayoutDelay::new(100.0);
+
+        // before the warmup we stick with the initial delay
+        delay.update(0.0, 0.0);
+        assert_eq!(delay.target_ms(), 100.0);
+
+        // a perfectly steady stream only needs the minimum delay
+        for i in 1..JITTERS_DELAY_WINDOW {
+            delay.update(i as f64 * 10.0, i as f64 * 10.0);
+        }
+        assert_eq!(delay.target_ms(), JITTERS_MIN_DELAY_MS);
+    }
+
+    #[test]
+    fn test_jittery_stream() {
+        let mut delay = PlayoutDelay::new(100.0);
+
+        // every 4th packet shows up 60ms late
+        for i in 0..JITTERS_DELAY_WINDOW {
+            let lag = if i % 4 == 0 { 60.0 } else { 0.0 };
+            delay.update(i as f64 * 10.0 + lag, i as f64 * 10.0);
+        }
+        assert_eq!(delay.target_ms(), 60.0);
+
+        delay.set_bounds(10.0, 40.0).unwrap();
+        assert_eq!(delay.target_ms(), 40.0);
+
+        // the other way round is a mistake, and the bounds stay as they were
+        assert_eq!(
+            delay.set_bounds(50.0, 30.0),
+            Err(Error::BadDelayBounds {
+                min_ms: 50.0,
+                max_ms: 30.0
+            })
+        );
+        assert_eq!(delay.target_ms(), 40.0);
+    }
+
+    #[test]
+    fn test_min_transit() {
+        let mut delay = PlayoutDelay::new(100.0);
+        assert_eq!(delay.min_transit_ms(), 0.0);
+
+        // once the first packet has left the window, the fastest one can be slower than it was
+        for i in 0..=JITTERS_DELAY_WINDOW {
+            let lag = if i == 0 { 0.0 } else { 30.0 + (i % 4) as f64 };
+            delay.update(i as f64 * 10.0 + lag, i as f64 * 10.0);
+        }
+        assert_eq!(delay.min_transit_ms(), 30.0);
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::VecDeque;
+
+/* packet loss concealment by pitch waveform substitution, after ITU-T G.711 Appendix I
+ * when audio goes missing, the pitch period of what was played last is found by autocorrelation, and that
+ * period is played over and over, with a 1/4 period overlap-add at the seams so there are no clicks
+ * repeating one period for long sounds like a buzz, so it becomes 2 periods after 10ms and 3 after 20ms,
+ * from 10ms on it fades out, and after 60ms it's silence
+ * the first good audio after a loss is overlap-added with the synthetic audio it takes over from
+ * the constants are the ones for 8000Hz in the recommendation, scaled to the clock rate
+ * see: https://www.itu.int/rec/T-REC-G.711-199909-I/en
+ */
+
+const ATTENUATION: f32 = 0.2; // per 10ms, after the first 10ms
+const CORR_MIN_POWER: f32 = 250.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Params {
+    frame: usize, // 10ms
+    pitch_min: usize,
+    pitch_max: usize,
+    corr_len: usize,
+    ndec: usize, // the coarse pitch search looks at every ndec-th sample
+    history_len: usize,
+    overlap_incr: usize, // the overlap with good audio grows by this for every 10ms concealed
+}
+
+impl Params {
+    fn new(clock_rate: u32) -> Self {
+        let scaled = |samples_at_8000: usize| {
+            ((samples_at_8000 * clock_rate as usize) as f64 / 8000.0).round() as usize
+        };
+        let pitch_max = scaled(120).max(4);
+        Params {
+            frame: scaled(80).max(1),
+            pitch_min: scaled(40).max(1),
+            pitch_max,
+            corr_len: scaled(160).max(1),
+            ndec: scaled(2).max(1),
+            history_len: pitch_max * 3 + pitch_max / 4,
+            overlap_incr: scaled(32),
+        }
+    }
+}
+
+// crossfade from l to r into out
+fn overlap_add(l: &[f32], r: &[f32], out: &mut [f32]) {
+    let incr = 1.0 / out.len() as f32;
+    let mut lw = 1.0 - incr;
+    let mut rw = incr;
+    for ((o, l), r) in out.iter_mut().zip(l).zip(r) {
+        *o = (lw * l + rw * r).clamp(-32768.0, 32767.0);
+        lw -= incr;
+        rw += incr;
+    }
+}
+
+struct ChannelPlc {
+    params: Params,
+    history: Vec<f32>,      // the last history_len samples played, oldest first
+    pitch_buf: Vec<f32>, // the history when the loss started, the last blen samples of it are repeated
+    last_quarter: Vec<f32>, // the end of the pitch buffer before it was smoothed
+    pitch: usize,
+    overlap: usize,
+    offset: usize,
+    blen: usize,
+    synth: VecDeque<f32>, // synthesized but not played yet, before the fade out
+    concealed: usize,     // samples played since the loss started
+    chunks: usize,        // 10ms chunks synthesized since the loss started
+}
+
+impl ChannelPlc {
+    fn new(params: Params) -> Self {
+        ChannelPlc {
+            params,
+            history: vec![0.0; params.history_len],
+            pitch_buf: vec![0.0; params.history_len],
+            last_quarter: Vec::new(),
+            pitch: 0,
+            overlap: 0,
+            offset: 0,
+            blen: 0,
+            synth: VecDeque::new(),
+            concealed: 0,
+            chunks: 0,
+        }
+    }
+
+    fn save(&mut self, samples: &[f32]) {
+        self.history.extend_from_slice(samples);
+        let excess = self.history.len() - self.params.history_len;
+        self.history.drain(..excess);
+    }
+
+    // the lag with the best normalized autocorrelation between the last corr_len samples and the ones before
+    // coarse on every ndec-th sample, then fine around the best coarse match
+    fn find_pitch(&self) -> usize {
+        let p = self.params;
+        let end = self.pitch_buf.len();
+        let l = &self.pitch_buf[end - p.corr_len..];
+        let pitch_diff = p.pitch_max - p.pitch_min;
+
+        let search = |from: usize, to: usize, step: usize| {
+            let mut best = (f32::MIN, from);
+            let mut j = from;
+            while j <= to {
+                let r = &self.pitch_buf[end - p.corr_len - p.pitch_max + j..];
+                let (mut energy, mut corr) = (0.0, 0.0);
+                for i in (0..p.corr_len).step_by(step) {
+                    energy += r[i] * r[i];
+                    corr += r[i] * l[i];
+                }
+                let corr = corr / energy.max(CORR_MIN_POWER).sqrt();
+                if corr > best.0 {
+                    best = (corr, j);
+                }
+                j += step;
+            }
+            best.1
+        };
+
+        let coarse = search(0, pitch_diff, p.ndec);
+        let fine = search(
+            coarse.saturating_sub(p.ndec - 1),
+            (coarse + p.ndec - 1).min(pitch_diff),
+            1,
+        );
+        p.pitch_max - fine
+    }
+
+    // more of the pitch buffer, from where we left off
+    fn repeat(&mut self, mut count: usize) -> Vec<f32> {
+        let start = self.pitch_buf.len() - self.blen;
+        let mut out = Vec::with_capacity(count);
+        while count > 0 {
+            let n = (self.blen - self.offset).min(count);
+            out.extend_from_slice(&self.pitch_buf[start + self.offset..start + self.offset + n]);
+            self.offset = (self.offset + n) % self.blen;
+            count -= n;
+        }
+        out
+    }
+
+    // the last 1/4 period of the pitch buffer fades into the samples a period before the repeated part
+    // so the end joins up with the start
+    fn smooth_pitch_buf(&mut self) {
+        let end = self.pitch_buf.len();
+        let start = end - self.blen;
+        let before_start = self.pitch_buf[start - self.overlap..start].to_vec();
+        overlap_add(
+            &self.last_quarter,
+            &before_start,
+            &mut self.pitch_buf[end - self.overlap..],
+        );
+    }
+
+    fn synthesize(&mut self) {
+        let frame = self.params.frame;
+        let chunk = match self.chunks {
+            0 => {
+                self.pitch_buf.copy_from_slice(&self.history);
+                self.pitch = self.find_pitch();
+                self.overlap = self.pitch / 4;
+                let end = self.pitch_buf.len();
+                self.last_quarter = self.pitch_buf[end - self.overlap..].to_vec();
+                self.offset = 0;
+                self.blen = self.pitch;
+                self.smooth_pitch_buf();
+                let history_end = self.history.len();
+                self.history[history_end - self.overlap..]
+                    .copy_from_slice(&self.pitch_buf[end - self.overlap..]);
+                self.repeat(frame)
+            }
+            1 | 2 => {
+                // one more period in the pitch buffer, faded in from the old one
+                let offset = self.offset;
+                let tail = self.repeat(self.overlap);
+                self.offset = offset;
+                while self.offset > self.pitch {
+                    self.offset -= self.pitch;
+                }
+                self.blen += self.pitch;
+                self.smooth_pitch_buf();
+                let mut chunk = self.repeat(frame);
+                let head = chunk[..self.overlap].to_vec();
+                overlap_add(&tail, &head, &mut chunk[..self.overlap]);
+                chunk
+            }
+            3..=5 => self.repeat(frame),
+            _ => vec![0.0; frame],
+        };
+        self.chunks += 1;
+        self.synth.extend(chunk);
+    }
+
+    fn conceal(&mut self, count: usize) -> Vec<f32> {
+        let frame = self.params.frame as f32;
+        while self.synth.len() < count {
+            self.synthesize();
+        }
+        let mut out = Vec::with_capacity(count);
+        for sample in self.synth.drain(..count) {
+            let fade = (self.concealed as f32 - frame).max(0.0) / frame;
+            out.push(sample * (1.0 - ATTENUATION * fade).max(0.0));
+            self.concealed += 1;
+        }
+        self.save(&out);
+        out
+    }
+
+    fn good(&mut self, samples: &mut [f32]) {
+        if self.chunks > 0 {
+            let p = self.params;
+            let olen = (self.overlap + (self.chunks - 1) * p.overlap_incr)
+                .min(p.frame)
+                .min(samples.len());
+            let mut synth = self.synth.drain(..).take(olen).collect::<Vec<_>>();
+            let more = olen - synth.len();
+            synth.extend(self.repeat(more));
+
+            // fade out the synthetic audio, from however loud it's got to, and fade in the real audio
+            let gain = (1.0 - (self.chunks - 1) as f32 * ATTENUATION).max(0.0);
+            let incr = 1.0 / olen as f32;
+            let mut lw = (1.0 - incr) * gain;
+            let mut rw = incr;
+            for (sample, synth) in samples.iter_mut().zip(synth) {
+                *sample = (lw * synth + rw * *sample).clamp(-32768.0, 32767.0);
+                lw -= incr * gain;
+                rw += incr;
+            }
+
+            self.synth.clear();
+            self.concealed = 0;
+            self.chunks = 0;
+        }
+        self.save(samples);
+    }
+}
+
+pub struct PitchPlc {
+    channels: Vec<ChannelPlc>,
+}
+
+impl PitchPlc {
+    pub fn new(clock_rate: u32, channels: u16) -> Self {
+        let params = Params::new(clock_rate);
+        PitchPlc {
+            channels: (0..channels).map(|_| ChannelPlc::new(params)).collect(),
+        }
+    }
+
+    // how many sample frames of history the pitch is found from
+    pub fn history_len(&self) -> usize {
+        self.channels[0].params.history_len
+    }
+
+    // in the middle of a loss, the next good audio will be overlap-added
+    pub fn concealing(&self) -> bool {
+        self.channels[0].chunks > 0
+    }
+
+    // interleaved PCM that did arrive, smoothed in place if it comes after concealed audio
+    pub fn good_frame(&mut self, pcm: &mut [i16]) {
+        let nchannels = self.channels.len();
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            let mut samples = pcm
+                .iter()
+                .skip(c)
+                .step_by(nchannels)
+                .map(|s| f32::from(*s))
+                .collect::<Vec<_>>();
+            channel.good(&mut samples);
+            for (i, sample) in samples.into_iter().enumerate() {
+                pcm[i * nchannels + c] = sample.round() as i16;
+            }
+        }
+    }
+
+    // interleaved PCM to play in place of this many sample frames that didn't arrive
+    pub fn conceal(&mut self, samples: usize) -> Vec<i16> {
+        let nchannels = self.channels.len();
+        let mut pcm = vec![0i16; samples * nchannels];
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            for (i, sample) in channel.conceal(samples).into_iter().enumerate() {
+                pcm[i * nchannels + c] = sample.round() as i16;
+            }
+        }
+        pcm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8000Hz, a period of 80 samples
+    fn sine(from: usize, to: usize) -> Vec<i16> {
+        (from..to)
+            .map(|i| (8000.0 * (2.0 * std::f32::consts::PI * i as f32 / 80.0).sin()) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn test_pitch() {
+        let mut plc = PitchPlc::new(8000, 1);
+        for i in 0..5 {
+            plc.good_frame(&mut sine(i * 80, (i + 1) * 80));
+        }
+        assert!(!plc.concealing());
+
+        // the first 10ms carry on with the same wave, more or less
+        let concealed = plc.conceal(80);
+        assert!(plc.concealing());
+        assert_eq!(plc.channels[0].pitch, 80);
+        let expected = sine(400, 480);
+        let err = concealed
+            .iter()
+            .zip(expected.iter())
+            .map(|(a, b)| (i32::from(*a) - i32::from(*b)).abs())
+            .max()
+            .unwrap();
+        assert!(err < 800, "off by {}", err);
+
+        // no clicks where the pitch buffer grows to 2 and 3 periods
+        let mut concealed = concealed;
+        concealed.extend(plc.conceal(160));
+        let step = concealed
+            .windows(2)
+            .map(|w| (i32::from(w[1]) - i32::from(w[0])).abs())
+            .max()
+            .unwrap();
+        assert!(step < 1000, "jumped by {}", step);
+    }
+
+    #[test]
+    fn test_fade_out() {
+        let mut plc = PitchPlc::new(8000, 2);
+        let mut pcm = sine(0, 400)
+            .into_iter()
+            .flat_map(|s| vec![s, -s])
+            .collect::<Vec<_>>();
+        plc.good_frame(&mut pcm);
+
+        // in bits that aren't 10ms
+        let mut concealed = Vec::new();
+        for _ in 0..20 {
+            concealed.extend(plc.conceal(28));
+        }
+        let peak = |from_ms: usize, to_ms: usize| {
+            concealed[from_ms * 16..to_ms * 16]
+                .iter()
+                .map(|s| s.abs())
+                .max()
+                .unwrap()
+        };
+        assert!(peak(0, 10) > 7000);
+        assert!(peak(30, 40) < peak(10, 20));
+        assert_eq!(peak(60, 70), 0);
+
+        // the channels are concealed separately
+        for frame in concealed.chunks(2) {
+            assert!((i32::from(frame[0]) + i32::from(frame[1])).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_overlap_add() {
+        let mut plc = PitchPlc::new(8000, 1);
+        plc.good_frame(&mut sine(0, 400));
+        plc.conceal(100); // ends on a peak
+
+        // a good frame that's silent, its start is blended in with the wave, the rest is left alone
+        let mut pcm = vec![0i16; 80];
+        plc.good_frame(&mut pcm);
+        assert!(!plc.concealing());
+        assert!(pcm[0] > 4000 && pcm[0] < 8000);
+        assert_eq!(pcm[60..], [0i16; 20]);
+    }
+}
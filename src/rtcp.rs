@@ -0,0 +1,843 @@
+#![allow(
+    clippy::unreadable_literal,
+    clippy::inconsistent_digit_grouping,
+    clippy::unusual_byte_groupings
+)]
+// i use ugly binary digit grouping to represent the RTCP header fields
+
+use crate::error::Error;
+use byteorder::{ByteOrder, NetworkEndian};
+use std::{
+    slice::Chunks,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const RTCP_SR: u8 = 200;
+pub const RTCP_RR: u8 = 201;
+pub const RTCP_SDES: u8 = 202;
+pub const RTCP_BYE: u8 = 203;
+pub const RTCP_APP: u8 = 204;
+
+// SDES item types
+pub const SDES_END: u8 = 0;
+pub const SDES_CNAME: u8 = 1;
+pub const SDES_NAME: u8 = 2;
+pub const SDES_EMAIL: u8 = 3;
+pub const SDES_PHONE: u8 = 4;
+pub const SDES_LOC: u8 = 5;
+pub const SDES_TOOL: u8 = 6;
+pub const SDES_NOTE: u8 = 7;
+pub const SDES_PRIV: u8 = 8;
+
+pub const RTCP_HEADER_SIZE: usize = 4;
+pub const RTCP_MAX_COUNT: usize = 31; // the count field is 5 bits, anything past it goes in another packet
+const REPORT_BLOCK_SIZE: usize = 24;
+
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800; // seconds between 1900 and 1970
+
+/*
+        0                   1                   2                   3
+        0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+header |V=2|P|    RC   |   PT=SR=200   |             length            |
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |                         SSRC of sender                        |
+       +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+sender |              NTP timestamp, most significant word             |
+info   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |             NTP timestamp, least significant word             |
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |                         RTP timestamp                         |
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |                     sender's packet count                     |
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |                      sender's octet count                     |
+       +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+report |                 SSRC_1 (SSRC of first source)                 |
+block  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+  1    | fraction lost |       cumulative number of packets lost       |
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |           extended highest sequence number received           |
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |                      interarrival jitter                      |
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |                         last SR (LSR)                         |
+       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+       |                   delay since last SR (DLSR)                  |
+       +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+
+the RR is the same without the sender info
+see: https://tools.ietf.org/html/rfc3550#section-6.4
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReportBlock {
+    pub ssrc: u32,
+    pub fraction_lost: u8,
+    pub cumulative_lost: i32, // 24 bits, signed
+    pub extended_highest_seq: u32,
+    pub jitter: u32,
+    pub last_sr: u32,
+    pub delay_since_last_sr: u32, // in units of 1/65536 seconds
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SenderReport {
+    pub ssrc: u32,
+    pub ntp_timestamp: u64,
+    pub rtp_timestamp: u32,
+    pub packet_count: u32,
+    pub octet_count: u32,
+    pub reports: Vec<ReportBlock>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReceiverReport {
+    pub ssrc: u32,
+    pub reports: Vec<ReportBlock>,
+}
+
+/*
+SDES: a list of chunks, each one is an SSRC/CSRC followed by |type|length|text...| items,
+terminated by a null item and padded to 32 bits
+see: https://tools.ietf.org/html/rfc3550#section-6.5
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdesItem {
+    pub item_type: u8,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SdesChunk {
+    pub ssrc: u32,
+    pub items: Vec<SdesItem>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceDescription {
+    pub chunks: Vec<SdesChunk>,
+}
+
+// see: https://tools.ietf.org/html/rfc3550#section-6.6
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bye {
+    pub ssrcs: Vec<u32>,
+    pub reason: Option<String>,
+}
+
+// see: https://tools.ietf.org/html/rfc3550#section-6.7
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct App {
+    pub subtype: u8,
+    pub ssrc: u32,
+    pub name: [u8; 4],
+    pub data: Vec<u8>, // a multiple of 32 bits
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RtcpPacket {
+    SenderReport(SenderReport),
+    ReceiverReport(ReceiverReport),
+    SourceDescription(SourceDescription),
+    Bye(Bye),
+    App(App),
+}
+
+// 64 bit NTP timestamp, seconds since 1900 in the upper 32 bits and the fraction in the lower
+pub fn ntp_timestamp(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let frac = (u64::from(since_epoch.subsec_nanos()) << 32) / 1_000_000_000;
+    (secs << 32) | frac
+}
+
+// the LSR field of a report block is the middle 32 bits of the SR's NTP timestamp
+pub fn ntp_middle_bits(ntp_timestamp: u64) -> u32 {
+    (ntp_timestamp >> 16) as u32
+}
+
+fn write_header(buf: &mut Vec<u8>, count: u8, packet_type: u8) {
+    buf.push(0b10_0_00000 | (count & 0b11111));
+    buf.push(packet_type);
+    buf.extend_from_slice(&[0u8, 0u8]); // the length is filled in by finish_packet
+}
+
+// pads to 32 bits and fills in the length, in 32-bit words minus one
+fn finish_packet(buf: &mut Vec<u8>, start: usize) {
+    let padded_len = (buf.len() + 3) & !3;
+    buf.resize(padded_len, 0);
+    let words = (buf.len() - start) / 4 - 1;
+    NetworkEndian::write_u16(&mut buf[start + 2..], words as u16);
+}
+
+// SDES items and BYE reasons have a one byte length, so longer text is cut to 255 bytes
+// on a char boundary, the other end gets valid UTF-8
+fn write_text(buf: &mut Vec<u8>, text: &str) {
+    let mut len = text.len().min(255);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf.push(len as u8);
+    buf.extend_from_slice(&text.as_bytes()[..len]);
+}
+
+// the items that fit in the first packet's count, and the rest in as many more packets as it takes
+// see: https://tools.ietf.org/html/rfc3550#section-6.4.2
+fn split_count<T>(items: &[T]) -> (&[T], Chunks<'_, T>) {
+    let (first, rest) = items.split_at(items.len().min(RTCP_MAX_COUNT));
+    (first, rest.chunks(RTCP_MAX_COUNT))
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    let mut word = [0u8; 4];
+    NetworkEndian::write_u32(&mut word, value);
+    buf.extend_from_slice(&word);
+}
+
+impl ReportBlock {
+    fn write(&self, buf: &mut Vec<u8>) {
+        write_u32(buf, self.ssrc);
+        write_u32(
+            buf,
+            (u32::from(self.fraction_lost) << 24) | (self.cumulative_lost as u32 & 0x00ff_ffff),
+        );
+        write_u32(buf, self.extended_highest_seq);
+        write_u32(buf, self.jitter);
+        write_u32(buf, self.last_sr);
+        write_u32(buf, self.delay_since_last_sr);
+    }
+
+    fn from_buf(buf: &[u8]) -> Self {
+        let lost = NetworkEndian::read_u32(&buf[4..]);
+        ReportBlock {
+            ssrc: NetworkEndian::read_u32(buf),
+            fraction_lost: (lost >> 24) as u8,
+            cumulative_lost: ((lost << 8) as i32) >> 8, // sign extend the 24 bits
+            extended_highest_seq: NetworkEndian::read_u32(&buf[8..]),
+            jitter: NetworkEndian::read_u32(&buf[12..]),
+            last_sr: NetworkEndian::read_u32(&buf[16..]),
+            delay_since_last_sr: NetworkEndian::read_u32(&buf[20..]),
+        }
+    }
+}
+
+fn read_report_blocks(buf: &[u8], count: usize) -> Result<Vec<ReportBlock>, Error> {
+    if buf.len() < count * REPORT_BLOCK_SIZE {
+        return Err(Error::TruncatedPacket(buf.len()));
+    }
+    Ok(buf
+        .chunks(REPORT_BLOCK_SIZE)
+        .take(count)
+        .map(ReportBlock::from_buf)
+        .collect())
+}
+
+fn write_receiver_report(buf: &mut Vec<u8>, ssrc: u32, reports: &[ReportBlock]) {
+    let start = buf.len();
+    write_header(buf, reports.len() as u8, RTCP_RR);
+    write_u32(buf, ssrc);
+    for report in reports {
+        report.write(buf);
+    }
+    finish_packet(buf, start);
+}
+
+// more than RTCP_MAX_COUNT report blocks go in RRs right after the report, which makes it a compound packet
+impl SenderReport {
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let (reports, rest) = split_count(&self.reports);
+        write_header(&mut buf, reports.len() as u8, RTCP_SR);
+        write_u32(&mut buf, self.ssrc);
+        write_u32(&mut buf, (self.ntp_timestamp >> 32) as u32);
+        write_u32(&mut buf, self.ntp_timestamp as u32);
+        write_u32(&mut buf, self.rtp_timestamp);
+        write_u32(&mut buf, self.packet_count);
+        write_u32(&mut buf, self.octet_count);
+        for report in reports {
+            report.write(&mut buf);
+        }
+        finish_packet(&mut buf, 0);
+        for reports in rest {
+            write_receiver_report(&mut buf, self.ssrc, reports);
+        }
+        buf
+    }
+}
+
+impl ReceiverReport {
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let (reports, rest) = split_count(&self.reports);
+        write_receiver_report(&mut buf, self.ssrc, reports);
+        for reports in rest {
+            write_receiver_report(&mut buf, self.ssrc, reports);
+        }
+        buf
+    }
+}
+
+impl SdesItem {
+    pub fn cname(cname: &str) -> Self {
+        SdesItem {
+            item_type: SDES_CNAME,
+            text: cname.to_string(),
+        }
+    }
+}
+
+fn write_sdes(buf: &mut Vec<u8>, chunks: &[SdesChunk]) {
+    let start = buf.len();
+    write_header(buf, chunks.len() as u8, RTCP_SDES);
+    for chunk in chunks {
+        write_u32(buf, chunk.ssrc);
+        for item in &chunk.items {
+            buf.push(item.item_type);
+            write_text(buf, &item.text);
+        }
+        // null item, then pad the chunk to 32 bits
+        buf.push(SDES_END);
+        let padded_len = (buf.len() + 3) & !3;
+        buf.resize(padded_len, 0);
+    }
+    finish_packet(buf, start);
+}
+
+// more than RTCP_MAX_COUNT chunks go in more SDES packets right after the first
+impl SourceDescription {
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let (chunks, rest) = split_count(&self.chunks);
+        write_sdes(&mut buf, chunks);
+        for chunks in rest {
+            write_sdes(&mut buf, chunks);
+        }
+        buf
+    }
+
+    fn from_body(body: &[u8], count: usize) -> Result<Self, Error> {
+        let mut chunks = Vec::new();
+        let mut i = 0;
+
+        for _ in 0..count {
+            if i + 4 > body.len() {
+                return Err(Error::TruncatedPacket(body.len()));
+            }
+            let mut chunk = SdesChunk {
+                ssrc: NetworkEndian::read_u32(&body[i..]),
+                items: Vec::new(),
+            };
+            i += 4;
+
+            loop {
+                match body.get(i) {
+                    None => return Err(Error::TruncatedPacket(body.len())),
+                    Some(&SDES_END) => break,
+                    Some(&item_type) => {
+                        let len =
+                            *body.get(i + 1).ok_or(Error::TruncatedPacket(body.len()))? as usize;
+                        if i + 2 + len > body.len() {
+                            return Err(Error::TruncatedPacket(body.len()));
+                        }
+                        chunk.items.push(SdesItem {
+                            item_type,
+                            text: String::from_utf8_lossy(&body[i + 2..i + 2 + len]).into_owned(),
+                        });
+                        i += 2 + len;
+                    }
+                }
+            }
+
+            // skip the null item and the padding up to the next 32 bit boundary
+            i = (i + 4) & !3;
+            chunks.push(chunk);
+        }
+
+        Ok(SourceDescription { chunks })
+    }
+
+    pub fn cname(&self, ssrc: u32) -> Option<&str> {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.ssrc == ssrc)
+            .flat_map(|chunk| chunk.items.iter())
+            .find(|item| item.item_type == SDES_CNAME)
+            .map(|item| item.text.as_str())
+    }
+}
+
+fn write_bye(buf: &mut Vec<u8>, ssrcs: &[u32], reason: Option<&str>) {
+    let start = buf.len();
+    write_header(buf, ssrcs.len() as u8, RTCP_BYE);
+    for ssrc in ssrcs {
+        write_u32(buf, *ssrc);
+    }
+    if let Some(reason) = reason {
+        write_text(buf, reason);
+    }
+    finish_packet(buf, start);
+}
+
+// more than RTCP_MAX_COUNT sources go in more BYE packets right after the first, each with the reason
+impl Bye {
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let (ssrcs, rest) = split_count(&self.ssrcs);
+        write_bye(&mut buf, ssrcs, self.reason.as_deref());
+        for ssrcs in rest {
+            write_bye(&mut buf, ssrcs, self.reason.as_deref());
+        }
+        buf
+    }
+
+    fn from_body(body: &[u8], count: usize) -> Result<Self, Error> {
+        if body.len() < 4 * count {
+            return Err(Error::TruncatedPacket(body.len()));
+        }
+        let ssrcs = body
+            .chunks(4)
+            .take(count)
+            .map(NetworkEndian::read_u32)
+            .collect();
+
+        let rest = &body[4 * count..];
+        let reason = match rest.first() {
+            Some(&len) if len > 0 => {
+                if 1 + len as usize > rest.len() {
+                    return Err(Error::TruncatedPacket(body.len()));
+                }
+                Some(String::from_utf8_lossy(&rest[1..=len as usize]).into_owned())
+            }
+            _ => None,
+        };
+
+        Ok(Bye { ssrcs, reason })
+    }
+}
+
+impl App {
+    pub fn to_buf(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_header(&mut buf, self.subtype, RTCP_APP);
+        write_u32(&mut buf, self.ssrc);
+        buf.extend_from_slice(&self.name);
+        buf.extend_from_slice(&self.data);
+        finish_packet(&mut buf, 0);
+        buf
+    }
+
+    fn from_body(body: &[u8], subtype: u8) -> Result<Self, Error> {
+        if body.len() < 8 {
+            return Err(Error::TruncatedPacket(body.len()));
+        }
+        let mut name = [0u8; 4];
+        name.copy_from_slice(&body[4..8]);
+        Ok(App {
+            subtype,
+            ssrc: NetworkEndian::read_u32(body),
+            name,
+            data: body[8..].to_vec(),
+        })
+    }
+}
+
+// see: https://tools.ietf.org/html/rfc3550#section-6.1
+pub fn build_compound(packets: &[RtcpPacket]) -> Result<Vec<u8>, Error> {
+    match packets.first() {
+        Some(RtcpPacket::SenderReport(_)) | Some(RtcpPacket::ReceiverReport(_)) => {}
+        _ => return Err(Error::BadCompoundPacket), // has to start with a report
+    }
+    Ok(packets.iter().flat_map(RtcpPacket::to_buf).collect())
+}
+
+// the individual packets of a compound RTCP packet, validated as in RFC 3550 Appendix A.2
+// packet types we don't know about are skipped
+pub fn split_compound(buf: &[u8]) -> Result<Vec<RtcpPacket>, Error> {
+    let mut packets = Vec::new();
+    let mut i = 0;
+
+    while i < buf.len() {
+        let (packet, len) = RtcpPacket::parse(&buf[i..])?;
+
+        if i == 0 && packet_type(&buf[i..]) != RTCP_SR && packet_type(&buf[i..]) != RTCP_RR {
+            return Err(Error::BadCompoundPacket);
+        }
+        if has_padding(&buf[i..]) && i + len != buf.len() {
+            return Err(Error::BadCompoundPacket); // only the last packet can be padded
+        }
+
+        if let Some(packet) = packet {
+            packets.push(packet);
+        }
+        i += len;
+    }
+
+    Ok(packets)
+}
+
+fn packet_type(buf: &[u8]) -> u8 {
+    buf[1]
+}
+
+fn has_padding(buf: &[u8]) -> bool {
+    ((buf[0] & 0b00_1_00000) >> 5) == 0b1
+}
+
+impl RtcpPacket {
+    pub fn from_buf(buf: &[u8]) -> Result<Self, Error> {
+        match RtcpPacket::parse(buf)? {
+            (Some(packet), _) => Ok(packet),
+            (None, _) => Err(Error::UnsupportedRtcpPacketType(packet_type(buf))),
+        }
+    }
+
+    // the packet at the start of buf if it's a type we know, and its length either way
+    fn parse(buf: &[u8]) -> Result<(Option<Self>, usize), Error> {
+        if buf.len() < RTCP_HEADER_SIZE {
+            return Err(Error::TruncatedPacket(buf.len()));
+        }
+
+        let version = buf[0] >> 6;
+        if version != 2 {
+            return Err(Error::BadVersion(version));
+        }
+
+        let count = (buf[0] & 0b00_0_11111) as usize;
+        let len = 4 * (NetworkEndian::read_u16(&buf[2..]) as usize + 1);
+        if buf.len() < len {
+            return Err(Error::TruncatedPacket(buf.len()));
+        }
+
+        let mut body_end = len;
+        if has_padding(buf) {
+            let padding = buf[len - 1] as usize;
+            if padding == 0 || padding > len - RTCP_HEADER_SIZE {
+                return Err(Error::BadPadding(padding as u8));
+            }
+            body_end -= padding;
+        }
+        let body = &buf[RTCP_HEADER_SIZE..body_end];
+
+        let packet = match packet_type(buf) {
+            RTCP_SR => {
+                if body.len() < 24 {
+                    return Err(Error::TruncatedPacket(buf.len()));
+                }
+                RtcpPacket::SenderReport(SenderReport {
+                    ssrc: NetworkEndian::read_u32(body),
+                    ntp_timestamp: NetworkEndian::read_u64(&body[4..]),
+                    rtp_timestamp: NetworkEndian::read_u32(&body[12..]),
+                    packet_count: NetworkEndian::read_u32(&body[16..]),
+                    octet_count: NetworkEndian::read_u32(&body[20..]),
+                    reports: read_report_blocks(&body[24..], count)?,
+                })
+            }
+            RTCP_RR => {
+                if body.len() < 4 {
+                    return Err(Error::TruncatedPacket(buf.len()));
+                }
+                RtcpPacket::ReceiverReport(ReceiverReport {
+                    ssrc: NetworkEndian::read_u32(body),
+                    reports: read_report_blocks(&body[4..], count)?,
+                })
+            }
+            RTCP_SDES => RtcpPacket::SourceDescription(SourceDescription::from_body(body, count)?),
+            RTCP_BYE => RtcpPacket::Bye(Bye::from_body(body, count)?),
+            RTCP_APP => RtcpPacket::App(App::from_body(body, count as u8)?),
+            _ => return Ok((None, len)),
+        };
+
+        Ok((Some(packet), len))
+    }
+
+    pub fn to_buf(&self) -> Vec<u8> {
+        match self {
+            RtcpPacket::SenderReport(sr) => sr.to_buf(),
+            RtcpPacket::ReceiverReport(rr) => rr.to_buf(),
+            RtcpPacket::SourceDescription(sdes) => sdes.to_buf(),
+            RtcpPacket::Bye(bye) => bye.to_buf(),
+            RtcpPacket::App(app) => app.to_buf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn report_block() -> ReportBlock {
+        ReportBlock {
+            ssrc: 0xcafe,
+            fraction_lost: 61,
+            cumulative_lost: -3, // duplicates can make it negative
+            extended_highest_seq: 0x1_0002,
+            jitter: 80,
+            last_sr: 0x1234_5678,
+            delay_since_last_sr: 65536,
+        }
+    }
+
+    #[test]
+    fn test_sender_report() {
+        let sr = SenderReport {
+            ssrc: 0xbeef,
+            ntp_timestamp: 0xdead_beef_0000_0001,
+            rtp_timestamp: 160,
+            packet_count: 2,
+            octet_count: 320,
+            reports: vec![report_block()],
+        };
+
+        let buf = sr.to_buf();
+        assert_eq!(buf.len(), 28 + 24);
+        assert_eq!(buf[0], 0b10_0_00001);
+        assert_eq!(buf[1], RTCP_SR);
+        assert_eq!(NetworkEndian::read_u16(&buf[2..]), 12);
+
+        assert_eq!(RtcpPacket::from_buf(&buf), Ok(RtcpPacket::SenderReport(sr)));
+    }
+
+    #[test]
+    fn test_receiver_report() {
+        let rr = ReceiverReport {
+            ssrc: 0xbeef,
+            reports: vec![report_block(), report_block()],
+        };
+
+        let buf = rr.to_buf();
+        assert_eq!(buf.len(), 8 + 48);
+        assert_eq!(
+            RtcpPacket::from_buf(&buf),
+            Ok(RtcpPacket::ReceiverReport(rr))
+        );
+
+        assert_eq!(
+            RtcpPacket::from_buf(&buf[..20]),
+            Err(Error::TruncatedPacket(20))
+        );
+    }
+
+    #[test]
+    fn test_too_many_reports() {
+        // only the first 31 fit, the rest go in another RR
+        let reports = (0..40)
+            .map(|ssrc| ReportBlock {
+                ssrc,
+                ..report_block()
+            })
+            .collect::<Vec<_>>();
+
+        let rr = ReceiverReport {
+            ssrc: 0xbeef,
+            reports: reports.clone(),
+        };
+        let buf = rr.to_buf();
+        assert_eq!(buf[0] & 0b11111, 31);
+        assert_eq!(buf.len(), 8 + 31 * 24 + 8 + 9 * 24);
+        assert_eq!(
+            split_compound(&buf),
+            Ok(vec![
+                RtcpPacket::ReceiverReport(ReceiverReport {
+                    ssrc: 0xbeef,
+                    reports: reports[..31].to_vec(),
+                }),
+                RtcpPacket::ReceiverReport(ReceiverReport {
+                    ssrc: 0xbeef,
+                    reports: reports[31..].to_vec(),
+                }),
+            ])
+        );
+
+        let sr = SenderReport {
+            ssrc: 0xbeef,
+            ntp_timestamp: 0,
+            rtp_timestamp: 0,
+            packet_count: 0,
+            octet_count: 0,
+            reports,
+        };
+        let buf = sr.to_buf();
+        assert_eq!(buf[0] & 0b11111, 31);
+        assert_eq!(buf.len(), 28 + 31 * 24 + 8 + 9 * 24);
+        let packets = split_compound(&buf).unwrap();
+        assert_eq!(packets.len(), 2);
+        match (&packets[0], &packets[1]) {
+            (RtcpPacket::SenderReport(first), RtcpPacket::ReceiverReport(rest)) => {
+                assert_eq!(first.reports, &sr.reports[..31]);
+                assert_eq!(rest.ssrc, 0xbeef);
+                assert_eq!(rest.reports, &sr.reports[31..]);
+            }
+            packets => panic!("expected an SR and an RR, got {:?}", packets),
+        }
+
+        // exactly 31 is still just the one packet
+        let rr = ReceiverReport {
+            ssrc: 0xbeef,
+            reports: sr.reports[..31].to_vec(),
+        };
+        assert_eq!(rr.to_buf().len(), 8 + 31 * 24);
+    }
+
+    #[test]
+    fn test_sdes() {
+        let sdes = SourceDescription {
+            chunks: vec![
+                SdesChunk {
+                    ssrc: 0xbeef,
+                    items: vec![
+                        SdesItem::cname("jitters@127.0.0.1"),
+                        SdesItem {
+                            item_type: SDES_TOOL,
+                            text: "jitters".to_string(),
+                        },
+                    ],
+                },
+                SdesChunk {
+                    ssrc: 0xcafe,
+                    items: vec![SdesItem::cname("abc")], // 4 + 5 + 1 = exactly 10 bytes, padded to 12
+                },
+            ],
+        };
+
+        let buf = sdes.to_buf();
+        assert_eq!(buf.len() % 4, 0);
+        assert_eq!(
+            RtcpPacket::from_buf(&buf),
+            Ok(RtcpPacket::SourceDescription(sdes.clone()))
+        );
+        assert_eq!(sdes.cname(0xcafe), Some("abc"));
+        assert_eq!(sdes.cname(0x1234), None);
+
+        let sdes = SourceDescription {
+            chunks: (0..40)
+                .map(|ssrc| SdesChunk {
+                    ssrc,
+                    items: vec![SdesItem::cname(&"x".repeat(300))],
+                })
+                .collect(),
+        };
+        let buf = sdes.to_buf();
+        assert_eq!(buf[0] & 0b11111, 31);
+
+        // past 31 chunks there's another SDES packet
+        let rr = RtcpPacket::ReceiverReport(ReceiverReport {
+            ssrc: 0xbeef,
+            reports: Vec::new(),
+        });
+        let packets =
+            split_compound(&build_compound(&[rr, RtcpPacket::SourceDescription(sdes)]).unwrap())
+                .unwrap();
+        assert_eq!(packets.len(), 3);
+        match (&packets[1], &packets[2]) {
+            (RtcpPacket::SourceDescription(first), RtcpPacket::SourceDescription(rest)) => {
+                assert_eq!(first.chunks.len(), 31);
+                assert_eq!(first.cname(30), Some("x".repeat(255).as_str()));
+                assert_eq!(rest.chunks.len(), 9);
+                assert_eq!(rest.cname(39), Some("x".repeat(255).as_str()));
+            }
+            packets => panic!("expected two SDES packets, got {:?}", packets),
+        }
+    }
+
+    #[test]
+    fn test_bye_and_app() {
+        for bye in &[
+            Bye {
+                ssrcs: vec![0xbeef, 0xcafe],
+                reason: Some("end of stream".to_string()),
+            },
+            Bye {
+                ssrcs: vec![0xbeef],
+                reason: None,
+            },
+        ] {
+            let buf = bye.to_buf();
+            assert_eq!(RtcpPacket::from_buf(&buf), Ok(RtcpPacket::Bye(bye.clone())));
+        }
+
+        // too many sources go in a second BYE, and too long a reason is cut to what fits
+        let bye = Bye {
+            ssrcs: (0..40).collect(),
+            reason: Some("é".repeat(200)),
+        };
+        let buf = bye.to_buf();
+        assert_eq!(buf[0] & 0b11111, 31);
+        let rr = RtcpPacket::ReceiverReport(ReceiverReport {
+            ssrc: 0xbeef,
+            reports: Vec::new(),
+        });
+        let packets =
+            split_compound(&build_compound(&[rr, RtcpPacket::Bye(bye)]).unwrap()).unwrap();
+        let byes = packets[1..]
+            .iter()
+            .map(|packet| match packet {
+                RtcpPacket::Bye(parsed) => parsed.clone(),
+                packet => panic!("expected a BYE, got {:?}", packet),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(byes.len(), 2);
+        assert_eq!(byes[0].ssrcs, (0..31).collect::<Vec<_>>());
+        assert_eq!(byes[1].ssrcs, (31..40).collect::<Vec<_>>());
+        for parsed in &byes {
+            assert_eq!(parsed.reason, Some("é".repeat(127))); // 254 bytes
+        }
+
+        let app = App {
+            subtype: 3,
+            ssrc: 0xbeef,
+            name: *b"JITR",
+            data: vec![1, 2, 3, 4],
+        };
+        let buf = app.to_buf();
+        assert_eq!(buf.len(), 16);
+        assert_eq!(RtcpPacket::from_buf(&buf), Ok(RtcpPacket::App(app)));
+    }
+
+    #[test]
+    fn test_compound() {
+        let packets = vec![
+            RtcpPacket::ReceiverReport(ReceiverReport {
+                ssrc: 0xbeef,
+                reports: vec![report_block()],
+            }),
+            RtcpPacket::SourceDescription(SourceDescription {
+                chunks: vec![SdesChunk {
+                    ssrc: 0xbeef,
+                    items: vec![SdesItem::cname("jitters@127.0.0.1")],
+                }],
+            }),
+            RtcpPacket::Bye(Bye {
+                ssrcs: vec![0xbeef],
+                reason: None,
+            }),
+        ];
+
+        let mut buf = build_compound(&packets).unwrap();
+        assert_eq!(split_compound(&buf), Ok(packets.clone()));
+
+        // has to start with a report
+        assert_eq!(build_compound(&packets[1..]), Err(Error::BadCompoundPacket));
+        assert_eq!(
+            split_compound(&packets[2].to_buf()),
+            Err(Error::BadCompoundPacket)
+        );
+
+        // unknown packet types get skipped
+        buf.extend_from_slice(&[0b10_0_00000, 210, 0, 0]);
+        assert_eq!(split_compound(&buf), Ok(packets));
+    }
+
+    #[test]
+    fn test_ntp_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_millis(1500);
+        let ntp = ntp_timestamp(time);
+
+        assert_eq!(ntp >> 32, NTP_UNIX_OFFSET + 1);
+        assert_eq!(ntp as u32, 1 << 31); // half a second
+        assert_eq!(
+            ntp_middle_bits(ntp),
+            (((NTP_UNIX_OFFSET + 1) as u32) << 16) | (1 << 15)
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use rand::{thread_rng, Rng};
+use std::{
+    f64::consts::E,
+    time::{Duration, Instant},
+};
+
+/* RTCP transmission interval, straight out of RFC 3550 Appendix A.7
+ * see: https://tools.ietf.org/html/rfc3550#section-6.3
+ *      https://tools.ietf.org/html/rfc3550#appendix-A.7
+ *
+ * the application polls this to find out when to send the next compound RTCP packet,
+ * and tells it about the packets it sent and the members coming and going
+ */
+pub const RTCP_BANDWIDTH_FRACTION: f64 = 0.05; // of the session bandwidth
+pub const RTCP_MIN_TIME: f64 = 5.0; // seconds
+const RTCP_SENDER_BW_FRACTION: f64 = 0.25;
+const RTCP_RCVR_BW_FRACTION: f64 = 0.75;
+const COMPENSATION: f64 = E - 1.5; // e - 3/2, for the "timer reconsideration" bias
+
+const INITIAL_RTCP_SIZE: f64 = 128.0; // a guess at our first compound packet, in bytes
+const IP_UDP_OVERHEAD: f64 = 28.0; // the RTCP packet size includes the lower layer headers
+
+#[derive(Debug, PartialEq)]
+pub enum RtcpAction {
+    Wait(Instant),
+    SendReport,
+    SendBye,
+}
+
+#[derive(Debug)]
+pub struct RtcpScheduler {
+    tp: Instant,        // the last time an RTCP packet was transmitted
+    tn: Instant,        // the next scheduled transmission time
+    pmembers: u32, // the estimated number of session members at the time tn was last recomputed
+    members: u32,  // the most current estimate for the number of session members
+    senders: u32,  // the most current estimate for the number of senders in the session
+    rtcp_bw: f64,  // the target RTCP bandwidth, in octets per second
+    we_sent: bool, // true if the application has sent data since the 2nd previous RTCP report
+    avg_rtcp_size: f64, // the average compound RTCP packet size, in octets
+    initial: bool, // true if the application has not yet sent an RTCP packet
+    leaving: bool, // a BYE is waiting to go out
+}
+
+impl RtcpScheduler {
+    // session bandwidth in octets per second, e.g. the RTP payload + headers of all senders
+    pub fn new(session_bw: f64, now: Instant) -> Self {
+        let mut scheduler = RtcpScheduler {
+            tp: now,
+            tn: now,
+            pmembers: 1,
+            members: 1,
+            senders: 0,
+            rtcp_bw: session_bw * RTCP_BANDWIDTH_FRACTION,
+            we_sent: false,
+            avg_rtcp_size: INITIAL_RTCP_SIZE + IP_UDP_OVERHEAD,
+            initial: true,
+            leaving: false,
+        };
+        scheduler.tn = now + scheduler.interval();
+        scheduler
+    }
+
+    // the randomized, compensated interval until the next RTCP packet
+    pub fn interval(&self) -> Duration {
+        let rtcp_min_time = if self.initial {
+            RTCP_MIN_TIME / 2.0
+        } else {
+            RTCP_MIN_TIME
+        };
+
+        // dedicate a fraction of the RTCP bandwidth to senders unless the number of senders is large enough
+        // that their share is more than that fraction
+        let mut n = f64::from(self.members);
+        let mut rtcp_bw = self.rtcp_bw;
+        if f64::from(self.senders) <= f64::from(self.members) * RTCP_SENDER_BW_FRACTION {
+            if self.we_sent {
+                rtcp_bw *= RTCP_SENDER_BW_FRACTION;
+                n = f64::from(self.senders);
+            } else {
+                rtcp_bw *= RTCP_RCVR_BW_FRACTION;
+                n -= f64::from(self.senders);
+            }
+        }
+
+        // f64::max also takes care of a NaN from a zero bandwidth
+        let mut t = (self.avg_rtcp_size * n / rtcp_bw).max(rtcp_min_time);
+
+        // to avoid traffic bursts from unintended synchronization with other sites,
+        // we then pick our actual next report interval as a random number uniformly distributed between 0.5*t and 1.5*t
+        t *= thread_rng().gen_range(0.5, 1.5);
+        t /= COMPENSATION;
+
+        Duration::from_secs_f64(t)
+    }
+
+    pub fn next_transmission(&self) -> Instant {
+        self.tn
+    }
+
+    // call this whenever the timer fires (or just regularly), and send what it says to send, then call packet_sent
+    // if it can't be sent yet (e.g. we don't know where to), the next one is due an interval from now
+    pub fn poll(&mut self, now: Instant) -> RtcpAction {
+        if now < self.tn {
+            return RtcpAction::Wait(self.tn);
+        }
+
+        // timer reconsideration - the member count might have changed since tn was computed
+        let tn = self.tp + self.interval();
+        if tn <= now {
+            // re-armed, so that polling again before packet_sent doesn't say to send again right away
+            self.tn = now + self.interval();
+            if self.leaving {
+                RtcpAction::SendBye
+            } else {
+                RtcpAction::SendReport
+            }
+        } else {
+            self.tn = tn;
+            self.pmembers = self.members;
+            RtcpAction::Wait(tn)
+        }
+    }
+
+    // after sending the report (or the BYE) that poll asked for
+    pub fn packet_sent(&mut self, size: usize, now: Instant) {
+        self.update_avg_rtcp_size(size);
+        self.tp = now;
+        self.initial = false;
+        self.we_sent = false; // until the next data packet
+        self.tn = now + self.interval();
+        self.pmembers = self.members;
+    }
+
+    // RTCP from anyone else counts towards the average packet size too
+    pub fn packet_received(&mut self, size: usize) {
+        self.update_avg_rtcp_size(size);
+    }
+
+    fn update_avg_rtcp_size(&mut self, size: usize) {
+        self.avg_rtcp_size =
+            (size as f64 + IP_UDP_OVERHEAD) / 16.0 + self.avg_rtcp_size * 15.0 / 16.0;
+    }
+
+    pub fn data_sent(&mut self) {
+        self.we_sent = true;
+    }
+
+    // members and senders include ourselves
+    // once we're leaving only BYEs count, see bye_received
+    pub fn set_members(&mut self, members: u32, senders: u32) {
+        if self.leaving {
+            return;
+        }
+        self.members = members.max(1);
+        self.senders = senders.min(self.members);
+    }
+
+    pub fn members(&self) -> u32 {
+        self.members
+    }
+
+    // "reverse reconsideration" - when members leave, pull the next transmission in
+    // so we don't end up reporting too rarely for the smaller group
+    // while we're leaving it's the other way round: members counts the BYEs we've seen, so that our own BYE
+    // backs off further when lots of members leave at once (RFC 3550 section 6.3.7)
+    pub fn bye_received(&mut self, now: Instant) {
+        if self.leaving {
+            self.members += 1;
+            return;
+        }
+
+        if self.members > 1 {
+            self.members -= 1;
+        }
+
+        if self.members < self.pmembers {
+            let ratio = f64::from(self.members) / f64::from(self.pmembers);
+            let until_tn = self.tn.saturating_duration_since(now).as_secs_f64();
+            let since_tp = now.saturating_duration_since(self.tp).as_secs_f64();
+
+            self.tn = now + Duration::from_secs_f64(ratio * until_tn);
+            // tp can't be moved back past whatever Instant can represent, leave it be then
+            self.tp = now
+                .checked_sub(Duration::from_secs_f64(ratio * since_tp))
+                .unwrap_or(self.tp);
+            self.pmembers = self.members;
+        }
+    }
+
+    // we're leaving - returns true if the BYE can go out right away, which it can with fewer than 50 members,
+    // otherwise it backs off as in RFC 3550 section 6.3.7 and poll says when to send it
+    pub fn leave(&mut self, bye_size: usize, now: Instant) -> bool {
+        self.leaving = true;
+
+        if self.members < 50 {
+            return true;
+        }
+
+        self.tp = now;
+        self.members = 1;
+        self.pmembers = 1;
+        self.senders = 0;
+        self.initial = true;
+        self.we_sent = false;
+        self.avg_rtcp_size = bye_size as f64 + IP_UDP_OVERHEAD;
+        self.tn = now + self.interval();
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimum_interval() {
+        let now = Instant::now();
+
+        // 44100 L16 stereo, a couple of members - the bandwidth is plenty so the minimum kicks in
+        let mut scheduler = RtcpScheduler::new(176_400.0, now);
+        scheduler.set_members(2, 1);
+
+        for _ in 0..100 {
+            let initial = scheduler.interval().as_secs_f64();
+            assert!(initial >= 0.5 * RTCP_MIN_TIME / 2.0 / COMPENSATION);
+            assert!(initial <= 1.5 * RTCP_MIN_TIME / 2.0 / COMPENSATION);
+        }
+
+        assert_eq!(
+            scheduler.poll(now),
+            RtcpAction::Wait(scheduler.next_transmission())
+        );
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(scheduler.poll(later), RtcpAction::SendReport);
+        scheduler.packet_sent(100, later);
+
+        let next = scheduler.next_transmission() - later;
+        assert!(next.as_secs_f64() >= 0.5 * RTCP_MIN_TIME / COMPENSATION);
+        assert!(next.as_secs_f64() <= 1.5 * RTCP_MIN_TIME / COMPENSATION);
+    }
+
+    #[test]
+    fn test_large_session() {
+        let now = Instant::now();
+
+        // a low bandwidth session with lots of receivers has to report less often
+        let mut scheduler = RtcpScheduler::new(8000.0, now);
+        scheduler.set_members(1000, 1);
+        scheduler.poll(scheduler.next_transmission());
+
+        // 128 octets * 999 receivers / (8000 * 0.05 * 0.75)
+        let t = (INITIAL_RTCP_SIZE + IP_UDP_OVERHEAD) * 999.0 / (8000.0 * 0.05 * 0.75);
+        let interval = scheduler.interval().as_secs_f64();
+        assert!(interval >= 0.5 * t / COMPENSATION && interval <= 1.5 * t / COMPENSATION);
+
+        // everybody else leaves, the next report gets pulled in
+        let before = scheduler.next_transmission();
+        for _ in 0..999 {
+            scheduler.bye_received(now);
+        }
+        assert_eq!(scheduler.members(), 1);
+        assert!(scheduler.next_transmission() < before);
+    }
+
+    #[test]
+    fn test_leave() {
+        let now = Instant::now();
+        let mut scheduler = RtcpScheduler::new(176_400.0, now);
+        scheduler.set_members(2, 1);
+
+        assert!(scheduler.leave(64, now));
+
+        // a big session backs off first
+        let mut scheduler = RtcpScheduler::new(176_400.0, now);
+        scheduler.set_members(100, 1);
+
+        assert!(!scheduler.leave(64, now));
+        assert!(scheduler.next_transmission() > now);
+        assert_eq!(
+            scheduler.poll(now + Duration::from_secs(10)),
+            RtcpAction::SendBye
+        );
+    }
+
+    #[test]
+    fn test_poll_without_sending() {
+        let now = Instant::now();
+        let mut scheduler = RtcpScheduler::new(176_400.0, now);
+        scheduler.set_members(2, 1);
+
+        // nobody to send the report to yet, the next poll doesn't ask again straight away
+        let later = now + Duration::from_secs(10);
+        assert_eq!(scheduler.poll(later), RtcpAction::SendReport);
+        assert_eq!(
+            scheduler.poll(later),
+            RtcpAction::Wait(scheduler.next_transmission())
+        );
+        assert!(scheduler.next_transmission() > later);
+
+        let next = scheduler.next_transmission();
+        assert_eq!(scheduler.poll(next), RtcpAction::SendReport);
+    }
+
+    #[test]
+    fn test_bye_while_leaving() {
+        let now = Instant::now();
+        let mut scheduler = RtcpScheduler::new(8000.0, now);
+        scheduler.set_members(1000, 1);
+        assert!(!scheduler.leave(64, now));
+        assert_eq!(scheduler.members(), 1);
+
+        // a BYE storm - the others' BYEs count up, and the interval with them
+        for _ in 0..999 {
+            scheduler.bye_received(now);
+        }
+        assert_eq!(scheduler.members(), 1000);
+
+        // reports and data don't count any more
+        scheduler.set_members(2, 1);
+        assert_eq!(scheduler.members(), 1000);
+
+        // 64 octets * 1000 members / (8000 * 0.05 * 0.75), the initial minimum is way below that
+        let t = (64.0 + IP_UDP_OVERHEAD) * 1000.0 / (8000.0 * 0.05 * 0.75);
+        let interval = scheduler.interval().as_secs_f64();
+        assert!(interval >= 0.5 * t / COMPENSATION && interval <= 1.5 * t / COMPENSATION);
+    }
+}
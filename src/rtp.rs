@@ -1,26 +1,61 @@
 #![allow(clippy::unreadable_literal, clippy::inconsistent_digit_grouping)]
 // i use ugly binary digit grouping to represent the RTP header fields
 
+use crate::{
+    codec::{codec_for, BoxedCodec, Codec},
+    error::Error,
+    payload::PayloadFormat,
+    rtcp::{
+        build_compound, ntp_timestamp, split_compound, Bye, RtcpPacket, SdesChunk, SdesItem,
+        SenderReport, SourceDescription,
+    },
+    rtp_ext::{parse_extensions, write_extensions, RtpExtension},
+    rtp_source::RtpSource,
+    util::ms_since,
+};
 use byteorder::{ByteOrder, NetworkEndian};
 use rand::{thread_rng, Rng};
-use std::{cmp::min, mem::size_of};
+use std::{
+    cmp::min,
+    time::{Instant, SystemTime},
+};
 
 pub const JITTERS_MAX_PACKET_SIZE: usize = 1388; //some voodoo based on 1500 mtu
 
-pub const JITTERS_SAMPLE_RATE: u32 = 44100; //we're only using 44100 L16 for now
+pub const RTP_HEADER_SIZE: usize = 12; //the fixed part, without CSRCs or extensions
 
-pub struct RtpOutStream {
+pub const IP_UDP_HEADER_SIZE: usize = 28; //IPv4 + UDP, the rest of the MTU is ours
+
+pub const RTP_MAX_DATAGRAM_SIZE: usize = 65535; //the most UDP can carry, size receive buffers with this whatever the MTU
+
+pub const JITTERS_SAMPLE_RATE: u32 = 44100; //the static L16 payload types, other rates need a dynamic PT
+
+pub struct RtpOutStream<C: Codec = BoxedCodec> {
     flags: u16,
     sequence: u16,
     timestamp: u32,
     ssrc: u32,
+    extensions: Vec<RtpExtension>,
+    codec: C,
+    max_payload_size: usize,
+    samples_per_packet: usize, // sample frames, see set_ptime and set_mtu
+    pending: Vec<i16>,         // PCM that didn't make a whole packet yet
+    packet_count: u32,
+    octet_count: u32,
+    last_sent: Option<(SystemTime, u32)>, // wallclock and timestamp of the last packet, for SRs
+    cname: String,
 }
 
 pub struct RtpInStream {
     first_header: RtpHeader,
+    format: PayloadFormat,
     pub channels: u16,
-    pub audio_slices: Vec<(Vec<u8>, u16, u32)>,
+    pub audio_slices: Vec<(Vec<u8>, u32, u32)>,
     ended: bool,
+    first_arrival: Instant,
+    source: RtpSource,
+    seq_base: u32, // what the seq. numbers count from, the first packet's until a re-sync
+    tstamp_base: u32, // and the same for timestamps
 }
 
 /*
@@ -44,73 +79,292 @@ pub struct RtpHeader {
     pub(crate) sequence: u16,
     pub(crate) timestamp: u32,
     pub(crate) ssrc: u32,
+    pub(crate) csrcs: Vec<u32>,
+    pub(crate) extension_profile: u16,
+    pub(crate) extensions: Vec<RtpExtension>,
 }
 
 impl RtpOutStream {
+    // L16 44100 under the static payload types
     pub fn new(channels: u16) -> Self {
-        let sequence = thread_rng().gen::<u16>() >> 2; // avoid overflow for long audio clips
-        let timestamp = thread_rng().gen::<u32>() >> 2; // avoid overflow for long audio clips
-        let ssrc = thread_rng().gen::<u32>();
-
-        let flags: u16 = match channels {
-            1 => 0b10_0_0_0000_0_0001011,
-            2 => 0b10_0_0_0000_0_0001010,
-            //     VV P X CCCC M PPPPPPP
-            //see: https://tools.ietf.org/html/rfc3550#section-5.1
+        let payload_type = match channels {
+            1 => 11,
+            2 => 10,
             _ => panic!("unsupported payload type"),
         }; //PT: 10,11 for L16 44100 mono,stereo
 
-        RtpOutStream {
+        RtpOutStream::with_format(PayloadFormat::from_static(payload_type).unwrap()).unwrap()
+    }
+
+    // any other rate or channel count, usually under a dynamic payload type (96-127)
+    // the receiver has to be told the same format out of band, e.g. with SDP
+    // fails for the encodings we don't have a codec for, bring one with with_codec
+    pub fn with_format(format: PayloadFormat) -> Result<Self, Error> {
+        Ok(RtpOutStream::with_codec(codec_for(format)?))
+    }
+}
+
+impl<C: Codec> RtpOutStream<C> {
+    // the payload type comes from the codec's format
+    pub fn with_codec(codec: C) -> Self {
+        let sequence = thread_rng().gen::<u16>();
+        let timestamp = thread_rng().gen::<u32>();
+        let ssrc = thread_rng().gen::<u32>();
+
+        let flags: u16 = 0b10_0_0_0000_0_0000000 | u16::from(codec.format().payload_type());
+        //                 VV P X CCCC M PPPPPPP
+        //see: https://tools.ietf.org/html/rfc3550#section-5.1
+
+        let mut rtp_stream = RtpOutStream {
             flags,
             sequence,
             timestamp,
             ssrc,
+            extensions: Vec::new(),
+            codec,
+            max_payload_size: JITTERS_MAX_PACKET_SIZE,
+            samples_per_packet: 0,
+            pending: Vec::new(),
+            packet_count: 0,
+            octet_count: 0,
+            last_sent: None,
+            cname: format!("jitters-{:08x}", ssrc),
+        };
+
+        // as much as fits in JITTERS_MAX_PACKET_SIZE, or a single frame if we can't tell
+        // a payload cut at max_payload_size has to end on a frame, or the timestamps go out of step
+        rtp_stream.samples_per_packet = match rtp_stream.format().frame_size() {
+            Some(frame_size) => {
+                let samples = rtp_stream.frames_that_fit(JITTERS_MAX_PACKET_SIZE, frame_size);
+                rtp_stream.max_payload_size = samples * frame_size;
+                samples
+            }
+            None => rtp_stream.codec.frame_samples(),
+        };
+        rtp_stream
+    }
+
+    pub fn format(&self) -> &PayloadFormat {
+        self.codec.format()
+    }
+
+    // packetization time, e.g. 20ms, the usual SDP a=ptime values are 10, 20, 30 and 40
+    // it has to be a whole number of codec frames, and fit in the payload size the MTU leaves us
+    // see: https://tools.ietf.org/html/rfc4566#section-6
+    pub fn set_ptime(&mut self, ptime_ms: u32) -> Result<(), Error> {
+        let ticks = u64::from(ptime_ms) * u64::from(self.codec.clock_rate());
+        let samples = (ticks / 1000) as usize;
+        let frame_samples = self.codec.frame_samples();
+        if samples == 0
+            || samples as u64 * 1000 != ticks
+            || frame_samples == 0
+            || samples / frame_samples * frame_samples != samples
+        {
+            return Err(Error::BadPtime(ptime_ms));
+        }
+
+        if let Some(sample_size) = self.format().sample_size() {
+            let payload_size = samples * sample_size * self.format().channels() as usize;
+            if payload_size > self.max_payload_size {
+                return Err(Error::BadPtime(ptime_ms));
+            }
         }
+        self.samples_per_packet = samples;
+        Ok(())
     }
 
-    pub fn next_packet(&mut self, audio_slice: &[u8]) -> Vec<u8> {
-        let ret_size = min(JITTERS_MAX_PACKET_SIZE, audio_slice.len());
-        if ret_size > JITTERS_MAX_PACKET_SIZE {
-            panic!("nah")
+    // as many frames as fit in a packet on a link with this MTU, for the sample based codecs
+    // the header is sized as it is now, so set any extensions first
+    pub fn set_mtu(&mut self, mtu: usize) -> Result<(), Error> {
+        let frame_size = self
+            .format()
+            .frame_size()
+            .ok_or_else(|| Error::UnsupportedPayloadFormat(self.format().rtpmap()))?;
+        let header_size = self.construct_header().to_buf(&[]).len();
+        let max_payload_size = mtu.saturating_sub(IP_UDP_HEADER_SIZE + header_size);
+
+        let samples = self.frames_that_fit(max_payload_size, frame_size);
+        if samples == 0 {
+            return Err(Error::BadMtu(mtu));
         }
+        self.max_payload_size = samples * frame_size;
+        self.samples_per_packet = samples;
+        Ok(())
+    }
 
-        let hdr = self.construct_header();
+    fn frames_that_fit(&self, payload_size: usize, frame_size: usize) -> usize {
+        let samples = payload_size / frame_size;
+        match self.codec.frame_samples() {
+            0 => 0, // not a codec we can packetize by size
+            frame_samples => samples - samples % frame_samples,
+        }
+    }
+
+    // sample frames that go in each packet from packetize
+    pub fn samples_per_packet(&self) -> usize {
+        self.samples_per_packet
+    }
 
-        let mut ret = vec![0u8; ret_size + size_of::<RtpHeader>()];
+    pub fn ptime_ms(&self) -> f64 {
+        self.format().samples_to_ms(self.samples_per_packet)
+    }
 
-        NetworkEndian::write_u16(&mut ret, hdr.flags);
-        NetworkEndian::write_u16(&mut ret[2..], hdr.sequence);
-        NetworkEndian::write_u32(&mut ret[4..], hdr.timestamp);
-        NetworkEndian::write_u32(&mut ret[8..], hdr.ssrc);
+    // chop interleaved PCM into packets of samples_per_packet each, the remainder waits for more PCM
+    pub fn packetize(&mut self, pcm: &[i16]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(pcm);
 
-        ret[size_of::<RtpHeader>()..].copy_from_slice(audio_slice);
-        self.increment(audio_slice.len() as u32);
-        ret
+        let packet_len = self.samples_per_packet * self.format().channels() as usize;
+        let mut packets = Vec::new();
+        while self.pending.len() >= packet_len {
+            let frame = self.pending.drain(..packet_len).collect::<Vec<_>>();
+            packets.push(self.next_frame(&frame));
+        }
+        packets
     }
 
-    pub fn last_packet(&mut self, audio_slice: &[u8]) -> Vec<u8> {
-        let ret_size = min(JITTERS_MAX_PACKET_SIZE, audio_slice.len());
-        if ret_size > JITTERS_MAX_PACKET_SIZE {
-            panic!("nah")
+    // whatever PCM packetize is still holding on to, in a short packet
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            return None;
         }
+        let frame = self.pending.split_off(0);
+        Some(self.next_frame(&frame))
+    }
 
-        let hdr = self.construct_header();
+    // encode interleaved PCM with our codec and packetize it
+    // the PCM is i16, so L24 goes out as 16-in-24, send L24::pack'ed payloads with next_packet for the full depth
+    pub fn next_frame(&mut self, pcm: &[i16]) -> Vec<u8> {
+        let payload = self.codec.encode(pcm);
+        self.next_packet(&payload)
+    }
 
-        let mut ret = vec![0u8; ret_size + size_of::<RtpHeader>()];
+    pub fn next_packet(&mut self, audio_slice: &[u8]) -> Vec<u8> {
+        let ret_size = min(self.max_payload_size, audio_slice.len());
 
-        println!("End... set the marker bit");
-        NetworkEndian::write_u16(&mut ret, hdr.flags | 0b1_0000000); //set the Marker bit
-        NetworkEndian::write_u16(&mut ret[2..], hdr.sequence);
-        NetworkEndian::write_u32(&mut ret[4..], hdr.timestamp);
-        NetworkEndian::write_u32(&mut ret[8..], hdr.ssrc);
+        let hdr = self.construct_header();
 
-        ret[size_of::<RtpHeader>()..].copy_from_slice(audio_slice);
+        let ret = hdr.to_buf(&audio_slice[..ret_size]);
+        self.count_sent(ret_size);
+        let timestamp_delta = self.codec.payload_samples(&audio_slice[..ret_size]);
+        self.increment(timestamp_delta as u32);
         ret
     }
 
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    // someone else is using our SSRC - say BYE with the old one and carry on as a new source
+    // a new source starts with a random sequence and timestamp, and counts for SRs from 0
+    // the CNAME stays the same, so receivers can tell it's still us
+    // the new SSRC is none of in_use, i.e. the ones we know other sources have
+    // see: https://tools.ietf.org/html/rfc3550#section-8.2
+    //      https://tools.ietf.org/html/rfc3550#section-6.4.1
+    pub fn change_ssrc(&mut self, in_use: &[u32], now: SystemTime) -> Vec<u8> {
+        let bye = self.bye(Some("SSRC collision"), now);
+
+        let old_ssrc = self.ssrc;
+        while self.ssrc == old_ssrc || in_use.contains(&self.ssrc) {
+            self.ssrc = thread_rng().gen::<u32>();
+        }
+        self.sequence = thread_rng().gen::<u16>();
+        self.timestamp = thread_rng().gen::<u32>();
+        self.packet_count = 0;
+        self.octet_count = 0;
+        self.last_sent = None;
+
+        bye
+    }
+
+    // the canonical name that goes out in our SDES, should be user@host if we know it
+    pub fn set_cname(&mut self, cname: &str) {
+        self.cname = cname.to_string();
+    }
+
+    // compound RTCP packet of an SR with the NTP wallclock and the RTP timestamp that corresponds to it,
+    // and our CNAME
+    // see: https://tools.ietf.org/html/rfc3550#section-6.4.1
+    pub fn sender_report(&self, now: SystemTime) -> Vec<u8> {
+        build_compound(&self.report_packets(now)).unwrap()
+    }
+
+    // the end of the stream - the same as a sender report, with a BYE tacked on
+    // see: https://tools.ietf.org/html/rfc3550#section-6.6
+    pub fn bye(&self, reason: Option<&str>, now: SystemTime) -> Vec<u8> {
+        let mut packets = self.report_packets(now);
+        packets.push(RtcpPacket::Bye(Bye {
+            ssrcs: vec![self.ssrc],
+            reason: reason.map(str::to_string),
+        }));
+        build_compound(&packets).unwrap()
+    }
+
+    fn report_packets(&self, now: SystemTime) -> Vec<RtcpPacket> {
+        let rtp_timestamp = match self.last_sent {
+            Some((sent_at, timestamp)) => {
+                // extrapolate from the last packet we sent
+                let elapsed_ms = now
+                    .duration_since(sent_at)
+                    .map(|elapsed| elapsed.as_secs_f64() * 1000.0)
+                    .unwrap_or(0.0);
+                timestamp.wrapping_add(self.format().ms_to_samples(elapsed_ms) as u32)
+            }
+            None => self.timestamp,
+        };
+
+        vec![
+            RtcpPacket::SenderReport(SenderReport {
+                ssrc: self.ssrc,
+                ntp_timestamp: ntp_timestamp(now),
+                rtp_timestamp,
+                packet_count: self.packet_count,
+                octet_count: self.octet_count,
+                reports: Vec::new(),
+            }),
+            RtcpPacket::SourceDescription(SourceDescription {
+                chunks: vec![SdesChunk {
+                    ssrc: self.ssrc,
+                    items: vec![SdesItem::cname(&self.cname)],
+                }],
+            }),
+        ]
+    }
+
+    fn count_sent(&mut self, payload_len: usize) {
+        self.packet_count = self.packet_count.wrapping_add(1);
+        self.octet_count = self.octet_count.wrapping_add(payload_len as u32);
+        self.last_sent = Some((SystemTime::now(), self.timestamp));
+    }
+
+    // for packets that were built ahead of time and went out later, see pacer.rs
+    // so SRs extrapolate from when the packet was really sent
+    pub(crate) fn mark_sent(&mut self, timestamp: u32, sent_at: SystemTime) {
+        self.last_sent = Some((sent_at, timestamp));
+    }
+
+    // RFC 8285 header extension carried in every packet from now on, until it's cleared
+    // e.g. audio level is updated before each packet
+    pub fn set_extension(&mut self, id: u8, data: &[u8]) -> Result<(), Error> {
+        if id == 0 || id == 15 || data.len() > 255 {
+            return Err(Error::MalformedExtension);
+        }
+        match self.extensions.iter_mut().find(|ext| ext.id == id) {
+            Some(ext) => ext.data = data.to_vec(),
+            None => self.extensions.push(RtpExtension {
+                id,
+                data: data.to_vec(),
+            }),
+        }
+        Ok(())
+    }
+
+    pub fn clear_extensions(&mut self) {
+        self.extensions.clear();
+    }
+
     fn increment(&mut self, timestamp_delta: u32) {
-        self.timestamp += timestamp_delta;
-        self.sequence += 1;
+        self.timestamp = self.timestamp.wrapping_add(timestamp_delta);
+        self.sequence = self.sequence.wrapping_add(1);
     }
 
     fn construct_header(&self) -> RtpHeader {
@@ -119,68 +373,172 @@ impl RtpOutStream {
             ssrc: self.ssrc,
             timestamp: self.timestamp,
             sequence: self.sequence,
+            csrcs: Vec::new(),
+            extension_profile: 0,
+            extensions: self.extensions.clone(),
         }
     }
 }
 
 impl RtpInStream {
-    pub fn new(first_packet: &[u8]) -> Self {
-        let (first_header, first_audio) = RtpHeader::from_buf(first_packet);
+    pub fn new(first_packet: &[u8]) -> Result<Self, Error> {
+        RtpInStream::new_at(first_packet, Instant::now())
+    }
 
-        let channels: u16 = match first_header.flags & 0b1111111 {
-            0b1011 => 1,
-            0b1010 => 2,
-            _ => panic!("unsupported payload type"),
-        };
+    // only the static payload types can be worked out from the packet
+    pub fn new_at(first_packet: &[u8], arrival: Instant) -> Result<Self, Error> {
+        let (first_header, _) = RtpHeader::from_buf(first_packet)?;
+        let format = PayloadFormat::from_static(first_header.payload_type())?;
+        RtpInStream::with_format_at(first_packet, format, arrival)
+    }
+
+    // a stream we've been told the format of, e.g. L16/48000/2 under a dynamic payload type
+    pub fn with_format(first_packet: &[u8], format: PayloadFormat) -> Result<Self, Error> {
+        RtpInStream::with_format_at(first_packet, format, Instant::now())
+    }
+
+    pub fn with_format_at(
+        first_packet: &[u8],
+        format: PayloadFormat,
+        arrival: Instant,
+    ) -> Result<Self, Error> {
+        let (first_header, first_audio) = RtpHeader::from_buf(first_packet)?;
+
+        if first_header.payload_type() != format.payload_type() {
+            return Err(Error::PayloadTypeMismatch {
+                expected: format.payload_type(),
+                got: first_header.payload_type(),
+            });
+        }
+        let channels = format.channels();
 
-        let ended = ((first_header.flags & 0b1_0000000) >> 7) == 0b1;
+        let ended = first_header.marker();
         // M - marker bit is set
         // weird for a first packet...
 
-        let mut audio_slices: Vec<(Vec<u8>, u16, u32)> = Vec::new();
+        let mut audio_slices: Vec<(Vec<u8>, u32, u32)> = Vec::new();
 
-        audio_slices.push((first_audio, 0u16, 0u32));
+        audio_slices.push((first_audio, 0u32, 0u32));
 
-        RtpInStream {
+        let mut source = RtpSource::new(first_header.sequence);
+        source.update_jitter(0u32, first_header.timestamp); // arrival times are relative to this one
+        let seq_base = u32::from(first_header.sequence);
+        let tstamp_base = first_header.timestamp;
+
+        Ok(RtpInStream {
             first_header,
+            format,
             channels,
             audio_slices,
             ended,
-        }
+            first_arrival: arrival,
+            source,
+            seq_base,
+            tstamp_base,
+        })
     }
 
-    pub fn next_packet(&mut self, next_packet: &[u8]) {
-        let (next_header, next_audio) = RtpHeader::from_buf(next_packet);
+    pub fn next_packet(&mut self, next_packet: &[u8]) -> Result<(), Error> {
+        self.next_packet_at(next_packet, Instant::now())
+    }
 
-        if (next_header.flags & 0b11111111_0_1111111)
-            != (self.first_header.flags & 0b11111111_0_1111111)
-            || next_header.ssrc != self.first_header.ssrc
-        {
-            panic!("this packet might be from a different rtp stream")
+    pub fn next_packet_at(&mut self, next_packet: &[u8], arrival: Instant) -> Result<(), Error> {
+        let (next_header, next_audio) = RtpHeader::from_buf(next_packet)?;
+
+        if next_header.ssrc != self.first_header.ssrc {
+            return Err(Error::SsrcMismatch {
+                expected: self.first_header.ssrc,
+                got: next_header.ssrc,
+            });
+        }
+
+        if next_header.payload_type() != self.first_header.payload_type() {
+            return Err(Error::PayloadTypeMismatch {
+                expected: self.first_header.payload_type(),
+                got: next_header.payload_type(),
+            });
+        }
+
+        let resyncs = self.source.resyncs();
+        if !self.source.update_seq(next_header.sequence) {
+            return Ok(()); // wild sequence jump, RFC 3550 says to throw it away
+        }
+        if self.source.resyncs() != resyncs {
+            self.rebase(next_header.sequence, next_header.timestamp);
         }
 
         self.audio_slices.push((
             next_audio,
-            next_header.sequence - self.first_header.sequence,
-            next_header.timestamp - self.first_header.timestamp,
+            self.source
+                .extended_seq(next_header.sequence)
+                .wrapping_sub(self.seq_base),
+            next_header.timestamp.wrapping_sub(self.tstamp_base),
         ));
 
-        self.ended = ((next_header.flags & 0b1_0000000) >> 7) == 0b1;
+        self.ended = next_header.marker();
         // check the Marker bit again
 
-        /* we can do stream quality analysis later
-         * by iter over audio_slices, timestamps, sequences
-         * ensuring they're monotonically increasing - that's where jitter can be measured!
-         */
+        let arrival_ms = ms_since(self.first_arrival, arrival);
+        self.source.update_jitter(
+            self.format.ms_to_samples(arrival_ms) as u32,
+            next_header.timestamp,
+        );
+
+        Ok(())
+    }
+
+    // the source started its seq. numbers over, the packet that did it goes right after the ones we have
+    // encodings we can't count the samples of carry on from the same timestamp
+    fn rebase(&mut self, seq: u16, timestamp: u32) {
+        let frame_size = self.format.frame_size();
+        let (next_seq, next_tstamp) = match self.audio_slices.iter().max_by_key(|slice| slice.1) {
+            Some(last) => (
+                last.1 + 1,
+                last.2
+                    .wrapping_add(frame_size.map_or(0, |size| last.0.len() / size) as u32),
+            ),
+            None => (0, 0),
+        };
+        self.seq_base = u32::from(seq).wrapping_sub(next_seq);
+        self.tstamp_base = timestamp.wrapping_sub(next_tstamp);
+    }
+
+    // a BYE from the sender ends the stream, the same as the marker bit
+    pub fn receive_rtcp(&mut self, buf: &[u8]) -> Result<(), Error> {
+        for packet in split_compound(buf)? {
+            if let RtcpPacket::Bye(bye) = packet {
+                if bye.ssrcs.contains(&self.first_header.ssrc) {
+                    self.ended = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn format(&self) -> &PayloadFormat {
+        &self.format
     }
 
     pub fn ended(&self) -> bool {
         self.ended
     }
+
+    // RFC 3550 interarrival jitter, in timestamp units
+    pub fn interarrival_jitter(&self) -> u32 {
+        self.source.jitter()
+    }
+
+    pub fn interarrival_jitter_ms(&self) -> f64 {
+        self.format.samples_to_ms(self.source.jitter() as usize)
+    }
 }
 
 impl RtpHeader {
-    pub fn from_buf(buf: &[u8]) -> (Self, Vec<u8>) {
+    pub fn from_buf(buf: &[u8]) -> Result<(Self, Vec<u8>), Error> {
+        if buf.len() < RTP_HEADER_SIZE {
+            return Err(Error::TruncatedPacket(buf.len()));
+        }
+
         let mut rtp_header = RtpHeader::default();
 
         rtp_header.flags = NetworkEndian::read_u16(&buf);
@@ -188,21 +546,143 @@ impl RtpHeader {
         rtp_header.timestamp = NetworkEndian::read_u32(&buf[4..]);
         rtp_header.ssrc = NetworkEndian::read_u32(&buf[8..]);
 
-        let mut audio_slice = vec![0u8; buf.len() - size_of::<RtpHeader>()];
+        if rtp_header.version() != 2 {
+            return Err(Error::BadVersion(rtp_header.version()));
+        }
+
+        let mut payload_start = RTP_HEADER_SIZE + 4 * rtp_header.csrc_count() as usize;
+        if buf.len() < payload_start {
+            return Err(Error::TruncatedPacket(buf.len()));
+        }
+
+        rtp_header.csrcs = buf[RTP_HEADER_SIZE..payload_start]
+            .chunks(4)
+            .map(NetworkEndian::read_u32)
+            .collect();
+
+        if rtp_header.extension() {
+            // 16 bits of profile-specific data, then the length of the extension in 32-bit words
+            if buf.len() < payload_start + 4 {
+                return Err(Error::TruncatedPacket(buf.len()));
+            }
+            let ext_start = payload_start + 4;
+            payload_start = ext_start + 4 * NetworkEndian::read_u16(&buf[ext_start - 2..]) as usize;
+            if buf.len() < payload_start {
+                return Err(Error::TruncatedPacket(buf.len()));
+            }
 
-        if !audio_slice.is_empty() {
-            audio_slice.copy_from_slice(&buf[size_of::<RtpHeader>()..]);
+            rtp_header.extension_profile = NetworkEndian::read_u16(&buf[ext_start - 4..]);
+            rtp_header.extensions =
+                parse_extensions(rtp_header.extension_profile, &buf[ext_start..payload_start])?;
         }
 
-        (rtp_header, audio_slice)
+        let mut payload_end = buf.len();
+
+        if rtp_header.padding() {
+            // the last octet of the padding is the count of padding octets, itself included
+            let padding = buf[buf.len() - 1];
+            if padding == 0 || (padding as usize) > buf.len() - payload_start {
+                return Err(Error::BadPadding(padding));
+            }
+            payload_end -= padding as usize;
+        }
+
+        Ok((rtp_header, buf[payload_start..payload_end].to_vec()))
+    }
+
+    // the CC and X bits are taken from the csrcs and extensions, not the flags
+    pub(crate) fn to_buf(&self, payload: &[u8]) -> Vec<u8> {
+        let mut flags = self.flags & 0b11_1_0_0000_1_1111111;
+        flags |= (self.csrcs.len() as u16 & 0b1111) << 8;
+
+        let ext_block = if self.extensions.is_empty() {
+            Vec::new()
+        } else {
+            flags |= 0b00_0_1_0000_0_0000000;
+            write_extensions(&self.extensions).expect("extensions are checked in set_extension")
+        };
+
+        let mut ret = vec![0u8; RTP_HEADER_SIZE];
+
+        NetworkEndian::write_u16(&mut ret, flags);
+        NetworkEndian::write_u16(&mut ret[2..], self.sequence);
+        NetworkEndian::write_u32(&mut ret[4..], self.timestamp);
+        NetworkEndian::write_u32(&mut ret[8..], self.ssrc);
+
+        for csrc in self.csrcs.iter().take(15) {
+            let mut csrc_buf = [0u8; 4];
+            NetworkEndian::write_u32(&mut csrc_buf, *csrc);
+            ret.extend_from_slice(&csrc_buf);
+        }
+
+        ret.extend_from_slice(&ext_block);
+        ret.extend_from_slice(payload);
+        ret
+    }
+
+    pub fn version(&self) -> u8 {
+        (self.flags >> 14) as u8
+    }
+
+    pub fn padding(&self) -> bool {
+        ((self.flags & 0b00_1_0_0000_0_0000000) >> 13) == 0b1
+    }
+
+    pub fn extension(&self) -> bool {
+        ((self.flags & 0b00_0_1_0000_0_0000000) >> 12) == 0b1
+    }
+
+    pub fn csrc_count(&self) -> u8 {
+        ((self.flags & 0b00_0_0_1111_0_0000000) >> 8) as u8
+    }
+
+    pub fn marker(&self) -> bool {
+        ((self.flags & 0b00_0_0_0000_1_0000000) >> 7) == 0b1
+    }
+
+    pub fn payload_type(&self) -> u8 {
+        (self.flags & 0b00_0_0_0000_0_1111111) as u8
+    }
+
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    pub fn csrcs(&self) -> &[u32] {
+        &self.csrcs
+    }
+
+    // the "defined by profile" field, 0xBEDE for RFC 8285 one-byte extensions
+    pub fn extension_profile(&self) -> u16 {
+        self.extension_profile
+    }
+
+    pub fn extensions(&self) -> &[RtpExtension] {
+        &self.extensions
+    }
+
+    pub fn find_extension(&self, id: u8) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|ext| ext.id == id)
+            .map(|ext| ext.data.as_slice())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{rtp_ext::*, rtp_jitter::RtpJitterInStream};
     use memoffset::offset_of;
-    use std::mem::size_of;
+    use std::{mem::size_of, time::Duration};
 
     #[test]
     fn test_max_packet_size() {
@@ -238,4 +718,394 @@ mod tests {
         println!("packet 1: {:#?}", packet_1);
         println!("packet 2: {:#?}", packet_2);
     }
+
+    #[test]
+    fn test_full_header() {
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            0b10_1_1_0010, 0b1_0001011, 0x12, 0x34, // V=2, P, X, CC=2, M, PT=11, sequence
+            0xde, 0xad, 0xbe, 0xef, // timestamp
+            0x00, 0x00, 0x00, 0x01, // ssrc
+            0x00, 0x00, 0x00, 0x02, // csrc 1
+            0x00, 0x00, 0x00, 0x03, // csrc 2
+            0xbe, 0xde, 0x00, 0x01, // extension profile, length of 1 word
+            0x10, 0xff, 0x00, 0x00, // extension data
+            1, 2, 3, 4, // the actual audio
+            0, 0, 3, // 3 octets of padding
+        ];
+
+        let (header, payload) = RtpHeader::from_buf(&packet).unwrap();
+
+        assert_eq!(header.version(), 2);
+        assert!(header.padding());
+        assert!(header.extension());
+        assert_eq!(header.csrc_count(), 2);
+        assert!(header.marker());
+        assert_eq!(header.payload_type(), 11);
+        assert_eq!(header.sequence(), 0x1234);
+        assert_eq!(header.timestamp(), 0xdeadbeef);
+        assert_eq!(header.ssrc(), 1);
+        assert_eq!(header.csrcs(), &[2, 3]);
+        assert_eq!(header.find_extension(1), Some(&[0xff][..]));
+        assert_eq!(payload, vec![1, 2, 3, 4]);
+
+        let mut bad_padding = packet.clone();
+        *bad_padding.last_mut().unwrap() = 20;
+        assert_eq!(
+            RtpHeader::from_buf(&bad_padding).unwrap_err(),
+            Error::BadPadding(20)
+        );
+
+        // cut off in the middle of the extension
+        assert_eq!(
+            RtpHeader::from_buf(&packet[..26]).unwrap_err(),
+            Error::TruncatedPacket(26)
+        );
+    }
+
+    #[test]
+    fn test_out_stream_extensions() {
+        let mut rtp_stream = RtpOutStream::new(2);
+        rtp_stream
+            .set_extension(1, &audio_level_data(true, 42))
+            .unwrap();
+        rtp_stream
+            .set_extension(3, &abs_send_time_data(0.25))
+            .unwrap();
+
+        let test_data = vec![1u8, 2u8, 3u8, 4u8];
+        let packet = rtp_stream.next_packet(&test_data);
+
+        let (header, payload) = RtpHeader::from_buf(&packet).unwrap();
+        assert!(header.extension());
+        assert_eq!(header.extension_profile(), RTP_EXT_ONE_BYTE_PROFILE);
+        assert_eq!(
+            parse_audio_level(header.find_extension(1).unwrap()),
+            Some((true, 42))
+        );
+        assert_eq!(
+            parse_abs_send_time(header.find_extension(3).unwrap()),
+            Some(0.25)
+        );
+        assert_eq!(payload, test_data);
+
+        // an id that only fits the two-byte format switches the whole packet over
+        rtp_stream.set_extension(100, &[]).unwrap();
+        let packet = rtp_stream.next_packet(&test_data);
+        let (header, payload) = RtpHeader::from_buf(&packet).unwrap();
+        assert_eq!(header.extension_profile(), RTP_EXT_TWO_BYTE_PROFILE);
+        assert_eq!(header.extensions().len(), 3);
+        assert_eq!(header.find_extension(100), Some(&[][..]));
+        assert_eq!(payload, test_data);
+
+        rtp_stream.clear_extensions();
+        let packet = rtp_stream.next_packet(&test_data);
+        let (header, _) = RtpHeader::from_buf(&packet).unwrap();
+        assert!(!header.extension());
+        assert_eq!(packet.len(), RTP_HEADER_SIZE + test_data.len());
+    }
+
+    #[test]
+    fn test_malformed_packets() {
+        let mut rtp_stream = RtpOutStream::new(1);
+        let packet = rtp_stream.next_packet(&[0u8; 4]);
+
+        assert_eq!(
+            RtpHeader::from_buf(&packet[..7]).unwrap_err(),
+            Error::TruncatedPacket(7)
+        );
+
+        let mut bad_version = packet.clone();
+        bad_version[0] &= 0b00_111111;
+        assert_eq!(
+            RtpInStream::new(&bad_version).err(),
+            Some(Error::BadVersion(0))
+        );
+
+        let mut bad_pt = packet.clone();
+        bad_pt[1] = 99;
+        assert_eq!(
+            RtpInStream::new(&bad_pt).err(),
+            Some(Error::UnsupportedPayloadType(99))
+        );
+
+        let mut rtp_in_stream = RtpInStream::new(&packet).unwrap();
+
+        let mut other_ssrc = rtp_stream.next_packet(&[0u8; 4]);
+        other_ssrc[8] ^= 0xff;
+        assert!(matches!(
+            rtp_in_stream.next_packet(&other_ssrc),
+            Err(Error::SsrcMismatch { .. })
+        ));
+
+        let mut stereo = RtpOutStream::new(2);
+        stereo.ssrc = rtp_stream.ssrc;
+        assert_eq!(
+            rtp_in_stream.next_packet(&stereo.next_packet(&[0u8; 4])),
+            Err(Error::PayloadTypeMismatch {
+                expected: 11,
+                got: 10
+            })
+        );
+
+        // nothing bad made it into the stream
+        assert_eq!(rtp_in_stream.audio_slices.len(), 1);
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let mut rtp_stream = RtpOutStream::new(1);
+        rtp_stream.sequence = 65534;
+        rtp_stream.timestamp = u32::MAX - 3;
+
+        let test_data = vec![0u8; 8]; // 4 mono samples
+        let packets = (0..4)
+            .map(|_| rtp_stream.next_packet(&test_data))
+            .collect::<Vec<_>>();
+
+        let mut rtp_in_stream = RtpInStream::new(&packets[0]).unwrap();
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new(&packets[0]).unwrap();
+
+        for packet in &packets[1..] {
+            rtp_in_stream.next_packet(packet).unwrap();
+        }
+
+        // the jitter stream gets them reordered across the wraparound
+        rtp_in_jitter_stream.next_packet(&packets[2]).unwrap();
+        rtp_in_jitter_stream.next_packet(&packets[1]).unwrap();
+        rtp_in_jitter_stream.next_packet(&packets[3]).unwrap();
+
+        for (i, slice) in rtp_in_stream.audio_slices.iter().enumerate() {
+            assert_eq!(slice.1, i as u32);
+            assert_eq!(slice.2, 4 * i as u32);
+        }
+        for (i, slice) in rtp_in_jitter_stream.audio_slices.values().enumerate() {
+            assert_eq!(slice.1, i as u32);
+            assert_eq!(slice.2, 4 * i as u32);
+        }
+    }
+
+    // the timestamp deltas ffplay and wireshark expect, one tick per sample frame at the clock rate
+    // see: https://tools.ietf.org/html/rfc3551#section-4.2
+    fn timestamp_deltas<C: Codec>(rtp_stream: &mut RtpOutStream<C>, payload: &[u8]) -> Vec<u32> {
+        let timestamps = (0..3)
+            .map(|_| {
+                let packet = rtp_stream.next_packet(payload);
+                RtpHeader::from_buf(&packet).unwrap().0.timestamp()
+            })
+            .collect::<Vec<_>>();
+        timestamps
+            .windows(2)
+            .map(|pair| pair[1].wrapping_sub(pair[0]))
+            .collect()
+    }
+
+    #[test]
+    fn test_timestamp_interop() {
+        // a full packet of 44100Hz L16 stereo is 347 sample frames, ~7.87ms
+        let mut rtp_stream = RtpOutStream::new(2);
+        let payload = vec![0u8; JITTERS_MAX_PACKET_SIZE];
+        assert_eq!(timestamp_deltas(&mut rtp_stream, &payload), vec![347, 347]);
+        assert_eq!(rtp_stream.format().samples_to_ms(347), 347.0 / 44.1);
+
+        let mut rtp_stream = RtpOutStream::new(1);
+        assert_eq!(timestamp_deltas(&mut rtp_stream, &payload), vec![694, 694]);
+
+        // 20ms of G.711 is 160 bytes and 160 ticks
+        let mut rtp_stream =
+            RtpOutStream::with_format(PayloadFormat::from_static(0).unwrap()).unwrap();
+        let payload = vec![0xffu8; 160];
+        assert_eq!(timestamp_deltas(&mut rtp_stream, &payload), vec![160, 160]);
+
+        // 4ms of 48000Hz stereo is 192 ticks whatever the sample size
+        for rtpmap in &["L8/48000/2", "L16/48000/2", "L24/48000/2"] {
+            let format = PayloadFormat::from_rtpmap(96, rtpmap).unwrap();
+            let payload = vec![0u8; 192 * format.frame_size().unwrap()];
+            let mut rtp_stream = RtpOutStream::with_format(format).unwrap();
+            assert_eq!(timestamp_deltas(&mut rtp_stream, &payload), vec![192, 192]);
+        }
+
+        // next_frame counts the PCM it's given
+        let mut rtp_stream = RtpOutStream::new(1);
+        let first = rtp_stream.next_frame(&[0i16; 441]);
+        let second = rtp_stream.next_frame(&[0i16; 441]);
+        let first = RtpHeader::from_buf(&first).unwrap().0.timestamp();
+        let second = RtpHeader::from_buf(&second).unwrap().0.timestamp();
+        assert_eq!(second.wrapping_sub(first), 441); // 10ms
+    }
+
+    #[test]
+    fn test_timestamp_frame_codec() {
+        // a codec that isn't sample based counts its own samples, e.g. 20ms opus frames
+        struct Framed(PayloadFormat);
+
+        impl Codec for Framed {
+            fn format(&self) -> &PayloadFormat {
+                &self.0
+            }
+
+            fn encode(&mut self, _pcm: &[i16]) -> Vec<u8> {
+                vec![0u8; 40]
+            }
+
+            fn decode(&mut self, _payload: &[u8]) -> Vec<i16> {
+                vec![0i16; 1920]
+            }
+
+            fn frame_samples(&self) -> usize {
+                960
+            }
+
+            fn payload_samples(&self, _payload: &[u8]) -> usize {
+                self.frame_samples()
+            }
+        }
+
+        let format = PayloadFormat::from_rtpmap(111, "opus/48000/2").unwrap();
+        let mut rtp_stream = RtpOutStream::with_codec(Framed(format));
+        assert_eq!(rtp_stream.codec.frame_duration_ms(), 20.0);
+        assert_eq!(
+            timestamp_deltas(&mut rtp_stream, &[0u8; 40]),
+            vec![960, 960]
+        );
+    }
+
+    #[test]
+    fn test_sender_report_timestamp() {
+        // the SR's RTP timestamp runs on the same clock as the packets
+        let mut rtp_stream = RtpOutStream::new(2);
+        let packet = rtp_stream.next_packet(&[0u8; 4]);
+        let sent = RtpHeader::from_buf(&packet).unwrap().0.timestamp();
+
+        let (sent_at, _) = rtp_stream.last_sent.unwrap();
+        let sr = rtp_stream.sender_report(sent_at + Duration::from_secs(1));
+        match &split_compound(&sr).unwrap()[0] {
+            RtcpPacket::SenderReport(sr) => {
+                assert_eq!(sr.rtp_timestamp.wrapping_sub(sent), 44100);
+            }
+            packet => panic!("expected an SR, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_ptime() {
+        let mut rtp_stream =
+            RtpOutStream::with_format(PayloadFormat::from_static(0).unwrap()).unwrap();
+        rtp_stream.set_ptime(20).unwrap();
+        assert_eq!(rtp_stream.samples_per_packet(), 160);
+        assert_eq!(rtp_stream.ptime_ms(), 20.0);
+
+        // 2.5 packets worth, the last half packet waits for more
+        let packets = rtp_stream.packetize(&[0i16; 400]);
+        assert_eq!(packets.len(), 2);
+        for packet in &packets {
+            assert_eq!(packet.len(), RTP_HEADER_SIZE + 160);
+        }
+        let packets = rtp_stream.packetize(&[0i16; 80]);
+        assert_eq!(packets.len(), 1);
+        assert!(rtp_stream.flush().is_none());
+
+        rtp_stream.packetize(&[0i16; 40]);
+        let last = rtp_stream.flush().unwrap();
+        assert_eq!(last.len(), RTP_HEADER_SIZE + 40);
+        assert!(rtp_stream.flush().is_none());
+
+        // 10ms of 44100 is 441 samples, 7ms isn't a whole number
+        let mut rtp_stream = RtpOutStream::new(1);
+        rtp_stream.set_ptime(10).unwrap();
+        assert_eq!(rtp_stream.samples_per_packet(), 441);
+        let packets = rtp_stream.packetize(&[0i16; 441]);
+        assert_eq!(packets[0].len(), RTP_HEADER_SIZE + 882);
+        assert_eq!(rtp_stream.set_ptime(7), Err(Error::BadPtime(7)));
+
+        // 20ms of 44100 mono doesn't fit in a 1500 byte MTU
+        assert_eq!(rtp_stream.set_ptime(20), Err(Error::BadPtime(20)));
+        rtp_stream.set_mtu(9000).unwrap();
+        rtp_stream.set_ptime(20).unwrap();
+        assert_eq!(rtp_stream.samples_per_packet(), 882);
+        assert_eq!(
+            rtp_stream.packetize(&[0i16; 882])[0].len(),
+            RTP_HEADER_SIZE + 1764
+        );
+    }
+
+    #[test]
+    fn test_mtu() {
+        // by default packets are as big as JITTERS_MAX_PACKET_SIZE allows
+        let mut rtp_stream = RtpOutStream::new(2);
+        assert_eq!(rtp_stream.samples_per_packet(), 347);
+
+        // 1500 - 28 - 12 = 1460 bytes of payload
+        rtp_stream.set_mtu(1500).unwrap();
+        assert_eq!(rtp_stream.samples_per_packet(), 365);
+        let packets = rtp_stream.packetize(&[0i16; 730]);
+        assert_eq!(packets[0].len() + IP_UDP_HEADER_SIZE, 1500);
+
+        // extensions eat into it
+        rtp_stream.set_extension(1, &[0x80]).unwrap();
+        rtp_stream.set_mtu(1500).unwrap();
+        assert_eq!(rtp_stream.samples_per_packet(), 363);
+
+        assert_eq!(rtp_stream.set_mtu(40), Err(Error::BadMtu(40)));
+    }
+
+    #[test]
+    fn test_frame_aligned_payload() {
+        // L24 stereo is 6 bytes a frame, and 1388 isn't a multiple of 6
+        let format = PayloadFormat::from_rtpmap(97, "L24/48000/2").unwrap();
+        let mut rtp_stream = RtpOutStream::with_format(format).unwrap();
+        assert_eq!(rtp_stream.samples_per_packet(), 231);
+        let packet = rtp_stream.next_packet(&[0u8; 2000]);
+        assert_eq!(packet.len(), RTP_HEADER_SIZE + 231 * 6);
+        assert_eq!(
+            timestamp_deltas(&mut rtp_stream, &[0u8; 2000]),
+            vec![231, 231]
+        );
+
+        // 1460 bytes of payload isn't either
+        rtp_stream.set_mtu(1500).unwrap();
+        let packet = rtp_stream.next_packet(&[0u8; 2000]);
+        assert_eq!(packet.len(), RTP_HEADER_SIZE + 243 * 6);
+        assert_eq!(
+            timestamp_deltas(&mut rtp_stream, &[0u8; 2000]),
+            vec![243, 243]
+        );
+    }
+
+    #[test]
+    fn test_ptime_no_frame_samples() {
+        // a codec that can't say how many samples a frame is can't have a ptime either
+        struct Unframed(PayloadFormat);
+
+        impl Codec for Unframed {
+            fn format(&self) -> &PayloadFormat {
+                &self.0
+            }
+
+            fn encode(&mut self, _pcm: &[i16]) -> Vec<u8> {
+                Vec::new()
+            }
+
+            fn decode(&mut self, _payload: &[u8]) -> Vec<i16> {
+                Vec::new()
+            }
+
+            fn frame_samples(&self) -> usize {
+                0
+            }
+
+            fn payload_samples(&self, _payload: &[u8]) -> usize {
+                0
+            }
+        }
+
+        let format = PayloadFormat::from_rtpmap(111, "opus/48000/2").unwrap();
+        let mut rtp_stream = RtpOutStream::with_codec(Unframed(format));
+        assert_eq!(rtp_stream.set_ptime(20), Err(Error::BadPtime(20)));
+
+        let format = PayloadFormat::from_rtpmap(96, "L16/8000").unwrap();
+        let mut rtp_stream = RtpOutStream::with_codec(Unframed(format));
+        assert_eq!(rtp_stream.set_ptime(20), Err(Error::BadPtime(20)));
+        assert_eq!(rtp_stream.set_mtu(1500), Err(Error::BadMtu(1500)));
+    }
 }
@@ -0,0 +1,224 @@
+use crate::error::Error;
+use byteorder::{ByteOrder, NetworkEndian};
+use std::collections::HashMap;
+
+/* RFC 8285 header extensions, in the "defined by profile" field of the RTP extension header
+ * see: https://tools.ietf.org/html/rfc8285#section-4
+ *
+ * one-byte:  0xBEDE | length, then elements of |  ID  | len-1 | data...
+ * two-byte:  0x100 + appbits | length, then elements of | ID | len | data...
+ */
+pub const RTP_EXT_ONE_BYTE_PROFILE: u16 = 0xBEDE;
+pub const RTP_EXT_TWO_BYTE_PROFILE: u16 = 0x1000; // the low 4 bits are "appbits"
+
+pub const RTP_EXT_AUDIO_LEVEL_URI: &str = "urn:ietf:params:rtp-hdrext:ssrc-audio-level";
+pub const RTP_EXT_ABS_SEND_TIME_URI: &str =
+    "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtpExtension {
+    pub id: u8,
+    pub data: Vec<u8>,
+}
+
+// maps the negotiated extension IDs (e.g. from an SDP extmap) to what they mean
+#[derive(Debug, Default)]
+pub struct RtpExtensionMap {
+    uris: HashMap<u8, String>,
+}
+
+impl RtpExtensionMap {
+    pub fn new() -> Self {
+        RtpExtensionMap::default()
+    }
+
+    pub fn register(&mut self, id: u8, uri: &str) -> Result<(), Error> {
+        if id == 0 || id == 15 {
+            return Err(Error::MalformedExtension); // reserved in both formats
+        }
+        self.uris.insert(id, uri.to_string());
+        Ok(())
+    }
+
+    pub fn uri(&self, id: u8) -> Option<&str> {
+        self.uris.get(&id).map(String::as_str)
+    }
+
+    pub fn id(&self, uri: &str) -> Option<u8> {
+        self.uris
+            .iter()
+            .find(|(_, registered)| registered.as_str() == uri)
+            .map(|(id, _)| *id)
+    }
+}
+
+// the profile and the extension data (without the 4 byte extension header)
+pub fn parse_extensions(profile: u16, buf: &[u8]) -> Result<Vec<RtpExtension>, Error> {
+    let one_byte = if profile == RTP_EXT_ONE_BYTE_PROFILE {
+        true
+    } else if profile & 0xfff0 == RTP_EXT_TWO_BYTE_PROFILE {
+        false
+    } else {
+        return Ok(Vec::new()); // some other profile's extension, not ours to parse
+    };
+
+    let mut extensions = Vec::new();
+    let mut i = 0;
+
+    while i < buf.len() {
+        if buf[i] == 0 {
+            i += 1; // padding between elements
+            continue;
+        }
+
+        let (id, len, data_start) = if one_byte {
+            let id = buf[i] >> 4;
+            if id == 15 {
+                break; // reserved, stop parsing
+            }
+            (id, (buf[i] & 0x0f) as usize + 1, i + 1)
+        } else {
+            if i + 1 >= buf.len() {
+                return Err(Error::MalformedExtension);
+            }
+            (buf[i], buf[i + 1] as usize, i + 2)
+        };
+
+        if data_start + len > buf.len() {
+            return Err(Error::MalformedExtension);
+        }
+
+        extensions.push(RtpExtension {
+            id,
+            data: buf[data_start..data_start + len].to_vec(),
+        });
+        i = data_start + len;
+    }
+
+    Ok(extensions)
+}
+
+// the full extension block including the 4 byte extension header, padded to 32 bits
+// the one-byte format is used whenever all the elements fit in it
+pub fn write_extensions(extensions: &[RtpExtension]) -> Result<Vec<u8>, Error> {
+    let one_byte = extensions
+        .iter()
+        .all(|ext| ext.id >= 1 && ext.id <= 14 && !ext.data.is_empty() && ext.data.len() <= 16);
+
+    let mut buf = vec![0u8; 4];
+
+    for ext in extensions {
+        if one_byte {
+            buf.push((ext.id << 4) | (ext.data.len() - 1) as u8);
+        } else {
+            if ext.id == 0 || ext.data.len() > 255 {
+                return Err(Error::MalformedExtension);
+            }
+            buf.push(ext.id);
+            buf.push(ext.data.len() as u8);
+        }
+        buf.extend_from_slice(&ext.data);
+    }
+
+    let padded_len = (buf.len() + 3) & !3;
+    buf.resize(padded_len, 0);
+
+    let profile = if one_byte {
+        RTP_EXT_ONE_BYTE_PROFILE
+    } else {
+        RTP_EXT_TWO_BYTE_PROFILE
+    };
+    let words = (buf.len() - 4) / 4;
+    NetworkEndian::write_u16(&mut buf, profile);
+    NetworkEndian::write_u16(&mut buf[2..], words as u16);
+
+    Ok(buf)
+}
+
+// RFC 6464 - voice activity flag and the level in -dBov (0 is the loudest, 127 silence)
+pub fn audio_level_data(voice: bool, level: u8) -> Vec<u8> {
+    vec![((voice as u8) << 7) | (level.min(127))]
+}
+
+pub fn parse_audio_level(data: &[u8]) -> Option<(bool, u8)> {
+    data.first().map(|b| (b >> 7 == 1, b & 0x7f))
+}
+
+// abs-send-time is a 6.18 fixed point number of seconds, wrapping every 64s
+pub fn abs_send_time_data(secs: f64) -> Vec<u8> {
+    let fixed = ((secs * f64::from(1 << 18)) as u64 & 0x00ff_ffff) as u32;
+    vec![(fixed >> 16) as u8, (fixed >> 8) as u8, fixed as u8]
+}
+
+pub fn parse_abs_send_time(data: &[u8]) -> Option<f64> {
+    if data.len() != 3 {
+        return None;
+    }
+    let fixed = NetworkEndian::read_u24(data);
+    Some(f64::from(fixed) / f64::from(1 << 18))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_byte_roundtrip() {
+        let extensions = vec![
+            RtpExtension {
+                id: 1,
+                data: audio_level_data(true, 30),
+            },
+            RtpExtension {
+                id: 3,
+                data: abs_send_time_data(1.5),
+            },
+        ];
+
+        let buf = write_extensions(&extensions).unwrap();
+        assert_eq!(NetworkEndian::read_u16(&buf), RTP_EXT_ONE_BYTE_PROFILE);
+        assert_eq!(NetworkEndian::read_u16(&buf[2..]), 2); // 2 + 4 bytes, padded to 8
+        assert_eq!(buf.len(), 4 + 8);
+
+        let parsed = parse_extensions(RTP_EXT_ONE_BYTE_PROFILE, &buf[4..]).unwrap();
+        assert_eq!(parsed, extensions);
+        assert_eq!(parse_audio_level(&parsed[0].data), Some((true, 30)));
+        assert_eq!(parse_abs_send_time(&parsed[1].data), Some(1.5));
+    }
+
+    #[test]
+    fn test_two_byte_roundtrip() {
+        // too long for the one-byte format
+        let extensions = vec![RtpExtension {
+            id: 20,
+            data: vec![7u8; 17],
+        }];
+
+        let buf = write_extensions(&extensions).unwrap();
+        assert_eq!(NetworkEndian::read_u16(&buf), RTP_EXT_TWO_BYTE_PROFILE);
+
+        let parsed = parse_extensions(RTP_EXT_TWO_BYTE_PROFILE | 0x3, &buf[4..]).unwrap();
+        assert_eq!(parsed, extensions);
+    }
+
+    #[test]
+    fn test_malformed() {
+        // says 4 bytes of data but there's only 2
+        assert_eq!(
+            parse_extensions(RTP_EXT_ONE_BYTE_PROFILE, &[0x13, 1, 2]),
+            Err(Error::MalformedExtension)
+        );
+    }
+
+    #[test]
+    fn test_extension_map() {
+        let mut map = RtpExtensionMap::new();
+        map.register(1, RTP_EXT_AUDIO_LEVEL_URI).unwrap();
+        map.register(3, RTP_EXT_ABS_SEND_TIME_URI).unwrap();
+
+        assert_eq!(map.id(RTP_EXT_ABS_SEND_TIME_URI), Some(3));
+        assert_eq!(map.uri(1), Some(RTP_EXT_AUDIO_LEVEL_URI));
+        assert_eq!(map.uri(2), None);
+        assert!(map.register(15, "urn:nope").is_err());
+    }
+}
@@ -1,139 +1,731 @@
 #![allow(clippy::unreadable_literal, clippy::inconsistent_digit_grouping)]
 // i use ugly binary digit grouping to represent the RTP header fields
 
-use crate::rtp::RtpHeader;
+use crate::{
+    codec::{codec_for, BoxedCodec, Codec},
+    error::Error,
+    payload::PayloadFormat,
+    playout::PlayoutDelay,
+    plc::PitchPlc,
+    rtcp::{
+        ntp_middle_bits, split_compound, ReceiverReport, ReportBlock, RtcpPacket, SenderReport,
+    },
+    rtp::RtpHeader,
+    rtp_source::RtpSource,
+    util::ms_since,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
 
-pub struct RtpJitterInStream {
+pub const JITTERS_PLAYOUT_DELAY_MS: u64 = 100; //initial playout delay, until we've measured the network jitter
+pub const JITTERS_MAX_CONCEALMENT_MS: u64 = 200; //conceal this much in a row and then the source has gone quiet
+const DUPLICATE_WINDOW: u32 = 64; //packets behind playout we still remember, to tell a duplicate from a late packet
+
+// what to do with a packet that turns up after its frame was played out (or concealed)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatePacketPolicy {
+    Discard,
+    // if it's the frame we just concealed, the next concealment carries on from the real audio instead
+    RefineConcealment,
+}
+
+// how much the buffer can hold, the oldest unplayed frame to the newest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferDepth {
+    Packets(usize),
+    Ms(f64),
+}
+
+// what to do with a packet that doesn't fit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    // skip ahead, the frames next in line are thrown away and playout carries on from the ones after them
+    DropOldest,
+    // the packet that doesn't fit is thrown away
+    DropNewest,
+    // throw everything away and start playout over from the packet that doesn't fit
+    FlushAndResync,
+}
+
+// how lost frames are filled in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Concealment {
+    // Codec::conceal, the last payload again unless the codec knows better
+    Codec,
+    // pitch waveform substitution on the decoded audio, see plc.rs
+    Pitch,
+}
+
+pub struct RtpJitterInStream<C: Codec = BoxedCodec> {
     first_header: RtpHeader,
+    codec: C,
     pub channels: u16,
-    pub audio_slices: Vec<(Vec<u8>, u16, u32)>,
+    pub audio_slices: BTreeMap<u32, (Vec<u8>, u32, u32)>, // keyed by sequence, counting from the first packet
     ended: bool,
     jitter: u32,
     plc: u32,
+    duplicates: u32,
+    received: BTreeSet<u32>, // the seqs that really arrived, back to DUPLICATE_WINDOW before playout
+    late: u32,
+    late_policy: LatePacketPolicy,
+    max_depth: Option<BufferDepth>,
+    overflow_policy: OverflowPolicy,
+    overflows: u32,
+    first_arrival: Instant,
+    playout_delay: PlayoutDelay,
+    playout_seq: u32,
+    playout_tstamp: u32,
+    skipped_ms: f64, // audio dropped on overflow, everything after it plays this much sooner
+    last_played: Option<Vec<u8>>,
+    last_concealed: bool,
+    concealed_ms: f64, // concealed since the last frame that really arrived
+    max_concealment_ms: f64,
+    pitch_plc: Option<PitchPlc>, // None to conceal with the codec
+    source: RtpSource,
+    seq_base: u32, // what the keys count from, the first packet's seq. until a re-sync
+    tstamp_base: u32, // and the same for timestamps
+    last_sr: Option<(u32, Instant)>, // middle bits of the last SR's NTP timestamp, and when it came in
 }
 
 impl RtpJitterInStream {
-    pub fn new(first_packet: &[u8]) -> Self {
-        let (first_header, first_audio) = RtpHeader::from_buf(first_packet);
+    pub fn new(first_packet: &[u8]) -> Result<Self, Error> {
+        RtpJitterInStream::new_at(first_packet, Instant::now())
+    }
 
-        let channels: u16 = match first_header.flags & 0b1111111 {
-            0b1011 => 1,
-            0b1010 => 2,
-            _ => panic!("unsupported payload type"),
-        };
+    // the arrival time of the first packet anchors the playout clock
+    // only the static payload types can be worked out from the packet
+    pub fn new_at(first_packet: &[u8], arrival: Instant) -> Result<Self, Error> {
+        let (first_header, _) = RtpHeader::from_buf(first_packet)?;
+        let format = PayloadFormat::from_static(first_header.payload_type())?;
+        RtpJitterInStream::with_format_at(first_packet, format, arrival)
+    }
+
+    // a stream we've been told the format of, e.g. L16/48000/2 under a dynamic payload type
+    pub fn with_format(first_packet: &[u8], format: PayloadFormat) -> Result<Self, Error> {
+        RtpJitterInStream::with_format_at(first_packet, format, Instant::now())
+    }
+
+    pub fn with_format_at(
+        first_packet: &[u8],
+        format: PayloadFormat,
+        arrival: Instant,
+    ) -> Result<Self, Error> {
+        let mut stream =
+            RtpJitterInStream::with_codec_at(first_packet, codec_for(format)?, arrival)?;
+        stream.set_concealment(Concealment::Pitch);
+        Ok(stream)
+    }
+}
+
+impl<C: Codec> RtpJitterInStream<C> {
+    // the payload type has to match the codec's format
+    pub fn with_codec(first_packet: &[u8], codec: C) -> Result<Self, Error> {
+        RtpJitterInStream::with_codec_at(first_packet, codec, Instant::now())
+    }
 
-        let ended = ((first_header.flags & 0b1_0000000) >> 7) == 0b1;
+    pub fn with_codec_at(first_packet: &[u8], codec: C, arrival: Instant) -> Result<Self, Error> {
+        let (first_header, first_audio) = RtpHeader::from_buf(first_packet)?;
+
+        if first_header.payload_type() != codec.format().payload_type() {
+            return Err(Error::PayloadTypeMismatch {
+                expected: codec.format().payload_type(),
+                got: first_header.payload_type(),
+            });
+        }
+        let channels = codec.format().channels();
+
+        let ended = first_header.marker();
         // M - marker bit is set
         // weird for a first packet...
 
-        let mut audio_slices: Vec<(Vec<u8>, u16, u32)> = Vec::new();
+        let mut audio_slices: BTreeMap<u32, (Vec<u8>, u32, u32)> = BTreeMap::new();
+
+        audio_slices.insert(0u32, (first_audio, 0u32, 0u32));
+
+        let mut playout_delay = PlayoutDelay::new(JITTERS_PLAYOUT_DELAY_MS as f64);
+        playout_delay.update(0.0, 0.0); // the first packet is the reference for all transit times
 
-        audio_slices.push((first_audio, 0u16, 0u32));
+        let mut source = RtpSource::new(first_header.sequence);
+        source.update_jitter(0u32, first_header.timestamp);
+        let seq_base = u32::from(first_header.sequence);
+        let tstamp_base = first_header.timestamp;
 
-        RtpJitterInStream {
+        Ok(RtpJitterInStream {
             first_header,
+            codec,
             channels,
             audio_slices,
             ended,
             jitter: 0u32,
             plc: 0u32,
-        }
+            duplicates: 0u32,
+            received: [0u32].iter().cloned().collect(),
+            late: 0u32,
+            late_policy: LatePacketPolicy::Discard,
+            max_depth: None,
+            overflow_policy: OverflowPolicy::DropOldest,
+            overflows: 0u32,
+            first_arrival: arrival,
+            playout_delay,
+            playout_seq: 0u32,
+            playout_tstamp: 0u32,
+            skipped_ms: 0.0,
+            last_played: None,
+            last_concealed: false,
+            concealed_ms: 0.0,
+            max_concealment_ms: JITTERS_MAX_CONCEALMENT_MS as f64,
+            pitch_plc: None,
+            source,
+            seq_base,
+            tstamp_base,
+            last_sr: None,
+        })
+    }
+
+    pub fn next_packet(&mut self, next_packet: &[u8]) -> Result<(), Error> {
+        self.next_packet_at(next_packet, Instant::now())
     }
 
-    pub fn next_packet(&mut self, next_packet: &[u8]) {
+    pub fn next_packet_at(&mut self, next_packet: &[u8], arrival: Instant) -> Result<(), Error> {
         if self.ended {
-            return;
+            return Ok(());
         }
-        let (next_header, next_audio) = RtpHeader::from_buf(next_packet);
+        let (next_header, next_audio) = RtpHeader::from_buf(next_packet)?;
 
-        if (next_header.flags & 0b11111111_0_1111111)
-            != (self.first_header.flags & 0b11111111_0_1111111)
-            || next_header.ssrc != self.first_header.ssrc
-        {
-            panic!("this packet might be from a different rtp stream")
+        if next_header.ssrc != self.first_header.ssrc {
+            return Err(Error::SsrcMismatch {
+                expected: self.first_header.ssrc,
+                got: next_header.ssrc,
+            });
         }
 
-        let next_seq = next_header.sequence - self.first_header.sequence; //decrement the random initial values
-        let next_tstamp = next_header.timestamp - self.first_header.timestamp;
+        if next_header.payload_type() != self.first_header.payload_type() {
+            return Err(Error::PayloadTypeMismatch {
+                expected: self.first_header.payload_type(),
+                got: next_header.payload_type(),
+            });
+        }
+
+        let resyncs = self.source.resyncs();
+        if !self.source.update_seq(next_header.sequence) {
+            return Ok(()); // wild sequence jump, RFC 3550 says to throw it away
+        }
+        if self.source.resyncs() != resyncs {
+            self.rebase(next_header.sequence, next_header.timestamp);
+        }
 
-        self.audio_slices.push((next_audio, next_seq, next_tstamp));
+        //decrement the random initial values
+        let next_seq = self
+            .source
+            .extended_seq(next_header.sequence)
+            .wrapping_sub(self.seq_base);
+        if (next_seq as i32) < 0 {
+            return Ok(()); // older than the packet that started the stream, there's nowhere to put it
+        }
 
-        let mut swap_idx: Option<usize> = None;
+        // a retransmission, or the same packet sent twice for redundancy, we've already got it
+        // or played it, which makes it a duplicate rather than late
+        if self.received.contains(&next_seq) || self.audio_slices.contains_key(&next_seq) {
+            self.duplicates += 1;
+            return Ok(());
+        }
+        self.received.insert(next_seq);
+        let horizon = self.playout_seq.saturating_sub(DUPLICATE_WINDOW);
+        if self.received.iter().next() < Some(&horizon) {
+            self.received = self.received.split_off(&horizon);
+        }
 
-        // check if the sequence is in order
-        for i in (0..self.audio_slices.len() - 1).rev() {
-            let seq_cmp = self.audio_slices[i].1;
-            //println!("comparing {} to {}", next_seq, seq_cmp);
-            if next_seq < seq_cmp {
-                swap_idx = Some(i);
-                continue;
+        // too late, its turn to play has come and gone
+        if next_seq < self.playout_seq {
+            self.late += 1;
+            if self.late_policy == LatePacketPolicy::RefineConcealment
+                && self.last_concealed
+                && next_seq + 1 == self.playout_seq
+            {
+                if let Some(ref mut pitch_plc) = self.pitch_plc {
+                    pitch_plc.good_frame(&mut self.codec.decode(&next_audio));
+                }
+                self.last_played = Some(next_audio);
+                self.last_concealed = false;
             }
-            break;
+            return Ok(());
         }
 
-        if let Some(swap_idx_) = swap_idx {
-            let last = self.audio_slices.pop().unwrap();
-            self.audio_slices.insert(swap_idx_, last);
+        let next_tstamp = next_header.timestamp.wrapping_sub(self.tstamp_base);
+
+        let arrival_ms = ms_since(self.first_arrival, arrival);
+
+        self.playout_delay.update(
+            arrival_ms,
+            self.codec.format().samples_to_ms(next_tstamp as usize),
+        );
+        self.source.update_jitter(
+            self.codec.format().ms_to_samples(arrival_ms) as u32,
+            next_header.timestamp,
+        );
+
+        if self.overflows_with(&next_audio) {
+            self.overflows += 1;
+            match self.overflow_policy {
+                OverflowPolicy::DropOldest => {
+                    while let Some(&oldest_seq) = self.audio_slices.keys().next() {
+                        if !self.overflows_with(&next_audio) {
+                            break;
+                        }
+                        let dropped = self.audio_slices.remove(&oldest_seq).unwrap();
+                        self.skip(&dropped);
+                    }
+                    if next_seq < self.playout_seq {
+                        return Ok(()); // it was in the gap we just skipped over
+                    }
+                }
+                OverflowPolicy::DropNewest => return Ok(()),
+                OverflowPolicy::FlushAndResync => {
+                    // the packet that overflowed plays after the target delay from now, as if it was the first one
+                    self.audio_slices.clear();
+                    self.playout_seq = next_seq;
+                    self.playout_tstamp = next_tstamp;
+                    self.skipped_ms = self.playout_delay.min_transit_ms()
+                        + self.codec.format().samples_to_ms(next_tstamp as usize)
+                        - arrival_ms;
+                    self.last_concealed = false;
+                }
+            }
+        }
+
+        // check if the sequence is in order, i.e. nothing after it has arrived yet
+        if self.audio_slices.range(next_seq..).next().is_some() {
             self.jitter += 1;
         }
 
+        self.audio_slices
+            .insert(next_seq, (next_audio, next_seq, next_tstamp));
+
         // check the Marker bit again
-        self.ended = ((next_header.flags & 0b1_0000000) >> 7) == 0b1;
+        self.ended = next_header.marker();
+
+        Ok(())
+    }
+
+    // would adding this payload take the buffer past its max depth
+    fn overflows_with(&self, next_audio: &[u8]) -> bool {
+        match self.max_depth {
+            None => false,
+            Some(BufferDepth::Packets(max_packets)) => self.audio_slices.len() + 1 > max_packets,
+            Some(BufferDepth::Ms(max_ms)) => {
+                let samples = self.codec.payload_samples(next_audio);
+                self.buffer_depth_ms() + self.codec.format().samples_to_ms(samples) > max_ms
+            }
+        }
+    }
 
-        /* we can do stream quality analysis later
-         * by iter over audio_slices, timestamps, sequences
-         * ensuring they're monotonically increasing - that's where jitter can be measured!
-         */
+    // a frame that won't be played, playout moves on past it without waiting for its turn
+    fn skip(&mut self, frame: &(Vec<u8>, u32, u32)) {
+        let samples = self.codec.payload_samples(&frame.0);
+        // along with whatever was missing before it, that isn't coming in time either
+        let skipped = frame.2.wrapping_sub(self.playout_tstamp) as usize + samples;
+        self.skipped_ms += self.codec.format().samples_to_ms(skipped);
+        self.playout_seq = frame.1 + 1;
+        self.playout_tstamp = frame.2.wrapping_add(samples as u32);
+        self.last_concealed = false;
     }
 
+    // for a stream that's all been received, e.g. after the marker bit, fills every gap at once
+    // live playout conceals each missing frame as its turn comes instead, see pop_frame
     pub fn plc(&mut self) {
         // we'll use Waveform substitution for packet loss concealment
-        // replace the missing sequences with a copy of the previous
+        // replace the missing sequences with repeats of the previous pitch period, or whatever the codec conceals with
+
+        // find the gaps first, the keys are in sequence order
+        let mut gaps: Vec<(u32, u32)> = Vec::new();
+        let mut seqs = self.audio_slices.keys();
+        if let Some(&first_seq) = seqs.next() {
+            let mut prev_seq = first_seq;
+            for &seq in seqs {
+                if seq - prev_seq > 1 {
+                    gaps.push((prev_seq, seq));
+                }
+                prev_seq = seq;
+            }
+        }
+
+        for (prev_seq, next_seq) in gaps {
+            if self.pitch_plc.is_some() {
+                self.pitch_fill(prev_seq, next_seq);
+                continue;
+            }
+            //need to fill in packets between prev and next - copies of prev, or whatever the codec conceals with
+            for seq in prev_seq + 1..next_seq {
+                let prev = &self.audio_slices[&(seq - 1)];
+                let concealed = self.codec.conceal(&prev.0);
+                let samples = self.codec.payload_samples(&prev.0); //the copy comes right after prev
+                let tstamp = prev.2.wrapping_add(samples as u32);
+                self.audio_slices.insert(seq, (concealed, seq, tstamp));
+                self.plc += 1; //increment plc counter
+            }
+        }
+    }
+
+    // the source started its seq. numbers over (the sender restarted, or the first packet was a stray)
+    // the packet that did it goes right after the ones we have, and its timestamp right after theirs,
+    // so playout carries on as if nothing happened
+    fn rebase(&mut self, seq: u16, timestamp: u32) {
+        let (next_seq, next_tstamp) = match self.audio_slices.iter().next_back() {
+            Some((&last_seq, last)) if last_seq >= self.playout_seq => (
+                last_seq + 1,
+                last.2
+                    .wrapping_add(self.codec.payload_samples(&last.0) as u32),
+            ),
+            _ => (self.playout_seq, self.playout_tstamp),
+        };
+        self.seq_base = u32::from(seq).wrapping_sub(next_seq);
+        self.tstamp_base = timestamp.wrapping_sub(next_tstamp);
+    }
 
-        let mut i: usize = 1;
+    // conceals the frames between prev and next, with a PitchPlc of its own since the frames aren't played in order
+    fn pitch_fill(&mut self, prev_seq: u32, next_seq: u32) {
+        let format = self.codec.format();
+        let mut pitch_plc = PitchPlc::new(format.clock_rate(), format.channels());
 
-        'outer: loop {
-            if i >= self.audio_slices.len() {
-                break 'outer;
+        // enough of the audio before the gap to find the pitch in
+        let mut history = Vec::new();
+        let mut samples = 0;
+        for (_, prev) in self.audio_slices.range(..=prev_seq).rev() {
+            if samples >= pitch_plc.history_len() {
+                break;
             }
+            samples += self.codec.payload_samples(&prev.0);
+            history.push(self.codec.decode(&prev.0));
+        }
+        for mut pcm in history.into_iter().rev() {
+            pitch_plc.good_frame(&mut pcm);
+        }
+
+        for seq in prev_seq + 1..next_seq {
+            let prev = &self.audio_slices[&(seq - 1)];
+            let samples = self.codec.payload_samples(&prev.0);
+            let tstamp = prev.2.wrapping_add(samples as u32);
+            let concealed = self.codec.encode(&pitch_plc.conceal(samples));
+            self.audio_slices.insert(seq, (concealed, seq, tstamp));
+            self.plc += 1;
+        }
+
+        // and the audio after it fades in
+        let next = self.audio_slices.get_mut(&next_seq).unwrap();
+        let mut pcm = self.codec.decode(&next.0);
+        pitch_plc.good_frame(&mut pcm);
+        next.0 = self.codec.encode(&pcm);
+    }
 
-            'inner: loop {
-                //compare two consecutive slices
-                let prev = &self.audio_slices[i - 1].clone();
-                let curr = &self.audio_slices[i].clone();
+    // a payload to play in place of the lost one after prev
+    fn conceal_after(&mut self, prev: &[u8]) -> Vec<u8> {
+        match self.pitch_plc {
+            Some(ref mut pitch_plc) => {
+                let samples = self.codec.payload_samples(prev);
+                self.codec.encode(&pitch_plc.conceal(samples))
+            }
+            None => self.codec.conceal(prev),
+        }
+    }
 
-                if curr.1 - prev.1 == 1 {
-                    break 'inner;
+    // a payload that did arrive, it's what concealment carries on from and it fades in after a loss
+    fn play_good(&mut self, payload: Vec<u8>) -> Vec<u8> {
+        match self.pitch_plc {
+            Some(ref mut pitch_plc) => {
+                let concealing = pitch_plc.concealing();
+                let mut pcm = self.codec.decode(&payload);
+                pitch_plc.good_frame(&mut pcm);
+                if concealing {
+                    self.codec.encode(&pcm)
+                } else {
+                    payload
                 }
-                //need to fill in packets between prev and curr - copies of prev
-                let mut to_ins = prev.clone();
-                to_ins.1 += 1; //pretend the copy has the correct sequence, so the logic of this inner/outer loop PLC can continue working in the next iterations
+            }
+            None => payload,
+        }
+    }
 
-                self.audio_slices.insert(i, to_ins);
-                i += 1;
-                self.plc += 1; //increment plc counter
+    pub fn pop_frame(&mut self, now: Instant) -> Option<(Vec<u8>, u32, u32)> {
+        // live playout - frames are pulled off the front of audio_slices in sequence order
+        // once their playout time (first arrival + delay + timestamp) has come
+        // a frame that still hasn't arrived by then is declared lost and concealed
+        // no marker bit needed, this works the same whether or not the stream ever ends
+
+        // anything older than what we've already played is useless now
+        if self.audio_slices.keys().next() < Some(&self.playout_seq) {
+            self.audio_slices = self.audio_slices.split_off(&self.playout_seq);
+        }
+
+        // after concealing for too long the source has gone quiet (silence suppression, or it stopped
+        // without a marker bit or a BYE), stop making things up and carry on from the next packet to arrive
+        if !self.audio_slices.contains_key(&self.playout_seq)
+            && self.concealed_ms >= self.max_concealment_ms
+        {
+            let (seq, tstamp) = self
+                .audio_slices
+                .values()
+                .next()
+                .map(|next| (next.1, next.2))?;
+            self.playout_seq = seq;
+            self.playout_tstamp = tstamp;
+        }
+
+        let tstamp = match self.audio_slices.get(&self.playout_seq) {
+            Some(next) => next.2,
+            None => self.playout_tstamp,
+        };
+        let have_next = self.audio_slices.contains_key(&self.playout_seq);
+
+        if now < self.playout_time(tstamp) {
+            return None;
+        }
+
+        let frame = if have_next {
+            self.concealed_ms = 0.0;
+            let mut frame = self.audio_slices.remove(&self.playout_seq).unwrap();
+            frame.0 = self.play_good(frame.0);
+            frame
+        } else {
+            if self.ended && self.audio_slices.is_empty() {
+                return None; // nothing left to wait for
             }
+            // same as plc()
+            let last_played = self.last_played.take()?;
+            let concealed = self.conceal_after(&last_played);
+            let samples = self.codec.payload_samples(&concealed);
+            self.concealed_ms += self.codec.format().samples_to_ms(samples);
+            self.plc += 1;
+            (concealed, self.playout_seq, tstamp)
+        };
 
-            i += 1;
+        self.playout_seq = frame.1 + 1;
+        let samples = self.codec.payload_samples(&frame.0); //the sender increments the timestamp by the sample frames in the payload
+        self.playout_tstamp = frame.2.wrapping_add(samples as u32);
+        self.last_played = Some(frame.0.clone());
+        self.last_concealed = !have_next;
+
+        Some(frame)
+    }
+
+    fn playout_time(&self, tstamp: u32) -> Instant {
+        // relative to the fastest packet we've seen, not the first one, in case the first one was slow
+        let playout_ms = self.playout_delay.min_transit_ms()
+            + self.playout_delay.target_ms()
+            + self.codec.format().samples_to_ms(tstamp as usize)
+            - self.skipped_ms;
+
+        let playout_offset = Duration::from_micros((playout_ms.abs() * 1000.0) as u64);
+        if playout_ms >= 0.0 {
+            self.first_arrival + playout_offset
+        } else {
+            // before the first arrival is as good as due now, even where Instant can't go that far back
+            self.first_arrival
+                .checked_sub(playout_offset)
+                .unwrap_or(self.first_arrival)
         }
     }
 
+    // the codecs that come with jitters default to pitch waveform substitution, and your own codec to its conceal
+    // except L24, the pitch PLC re-encodes the frames around a gap from i16 and that would cost them their low 8 bits
+    pub fn set_concealment(&mut self, concealment: Concealment) {
+        let format = self.codec.format();
+        self.pitch_plc = match concealment {
+            Concealment::Pitch if format.encoding_name() != "L24" => {
+                Some(PitchPlc::new(format.clock_rate(), format.channels()))
+            }
+            _ => None,
+        };
+    }
+
+    // how much to conceal in a row before deciding the source has gone quiet
+    pub fn set_max_concealment(&mut self, max_concealment_ms: f64) {
+        self.max_concealment_ms = max_concealment_ms;
+    }
+
+    pub fn set_late_policy(&mut self, late_policy: LatePacketPolicy) {
+        self.late_policy = late_policy;
+    }
+
+    // by default the buffer holds everything it's given, e.g. a whole file that's played after the marker bit
+    pub fn set_max_depth(
+        &mut self,
+        max_depth: Option<BufferDepth>,
+        overflow_policy: OverflowPolicy,
+    ) {
+        self.max_depth = max_depth;
+        self.overflow_policy = overflow_policy;
+    }
+
+    pub fn set_delay_bounds(&mut self, min_delay_ms: f64, max_delay_ms: f64) -> Result<(), Error> {
+        self.playout_delay.set_bounds(min_delay_ms, max_delay_ms)
+    }
+
+    // how long frames are currently held back before playout
+    pub fn target_delay_ms(&self) -> f64 {
+        self.playout_delay.target_ms()
+    }
+
+    // how much audio is sitting in the buffer waiting to be played
+    pub fn buffer_depth_ms(&self) -> f64 {
+        self.audio_slices
+            .range(self.playout_seq..)
+            .map(|(_, slice)| {
+                let samples = self.codec.payload_samples(&slice.0);
+                self.codec.format().samples_to_ms(samples)
+            })
+            .sum()
+    }
+
+    pub fn receive_sender_report(
+        &mut self,
+        sr: &SenderReport,
+        arrival: Instant,
+    ) -> Result<(), Error> {
+        if sr.ssrc != self.first_header.ssrc {
+            return Err(Error::SsrcMismatch {
+                expected: self.first_header.ssrc,
+                got: sr.ssrc,
+            });
+        }
+        self.last_sr = Some((ntp_middle_bits(sr.ntp_timestamp), arrival));
+        Ok(())
+    }
+
+    // SRs from the sender feed the LSR/DLSR of our reports, and a BYE ends the stream the same as the marker bit
+    pub fn receive_rtcp(&mut self, buf: &[u8], arrival: Instant) -> Result<(), Error> {
+        for packet in split_compound(buf)? {
+            match packet {
+                RtcpPacket::SenderReport(ref sr) if sr.ssrc == self.first_header.ssrc => {
+                    self.receive_sender_report(sr, arrival)?;
+                }
+                RtcpPacket::Bye(ref bye) if bye.ssrcs.contains(&self.first_header.ssrc) => {
+                    self.end();
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // reception report about this stream, this starts a new interval for the fraction lost
+    pub fn report_block(&mut self, now: Instant) -> ReportBlock {
+        let (last_sr, delay_since_last_sr) = match self.last_sr {
+            Some((last_sr, arrival)) => {
+                let delay = now.saturating_duration_since(arrival).as_secs_f64();
+                (last_sr, (delay * 65536.0) as u32)
+            }
+            None => (0, 0),
+        };
+
+        ReportBlock {
+            ssrc: self.first_header.ssrc,
+            fraction_lost: self.source.fraction_lost(),
+            cumulative_lost: self.source.cumulative_lost(),
+            extended_highest_seq: self.source.extended_max_seq(),
+            jitter: self.source.jitter(),
+            last_sr,
+            delay_since_last_sr,
+        }
+    }
+
+    // RTCP RR from us (the receiver's own ssrc) about this stream
+    pub fn receiver_report(&mut self, ssrc: u32, now: Instant) -> Vec<u8> {
+        ReceiverReport {
+            ssrc,
+            reports: vec![self.report_block(now)],
+        }
+        .to_buf()
+    }
+
+    // RFC 3550 interarrival jitter, in timestamp units
+    pub fn interarrival_jitter(&self) -> u32 {
+        self.source.jitter()
+    }
+
+    pub fn interarrival_jitter_ms(&self) -> f64 {
+        self.codec
+            .format()
+            .samples_to_ms(self.source.jitter() as usize)
+    }
+
+    pub fn format(&self) -> &PayloadFormat {
+        self.codec.format()
+    }
+
+    // decode a payload we've popped with the stream's codec
+    pub fn decode(&mut self, payload: &[u8]) -> Vec<i16> {
+        self.codec.decode(payload)
+    }
+
     pub fn ended(&self) -> bool {
         self.ended
     }
 
+    // the sender said BYE, e.g. in a compound packet someone else has already split up
+    // whatever's buffered still plays out, nothing after it goes in
+    pub fn end(&mut self) {
+        self.ended = true;
+    }
+
     pub fn jitter_stats(&self) -> String {
-        format!(
+        let mut stats = format!(
             "corrected {} out-of-order packets, concealed {} lost packets",
             self.jitter, self.plc
-        )
+        );
+        // the rest only when they've happened at all
+        if self.duplicates > 0 {
+            stats += &format!(", dropped {} duplicate packets", self.duplicates);
+        }
+        if self.late > 0 {
+            stats += &format!(", discarded {} late packets", self.late);
+        }
+        if self.overflows > 0 {
+            stats += &format!(", handled {} buffer overflows", self.overflows);
+        }
+        stats
+    }
+
+    // lost frames filled in, by plc or at playout
+    pub fn concealed(&self) -> u32 {
+        self.plc
+    }
+
+    pub fn duplicates(&self) -> u32 {
+        self.duplicates
+    }
+
+    // packets that arrived after their frame was played out or concealed
+    pub fn late(&self) -> u32 {
+        self.late
+    }
+
+    // times a packet didn't fit within the maximum depth
+    pub fn overflowed(&self) -> u32 {
+        self.overflows
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::rtp::*;
+    use crate::{
+        codec::{L16, L24},
+        g711::{decode_ulaw, encode_ulaw},
+        rtcp::RtcpPacket,
+        rtp::*,
+    };
+    use byteorder::{ByteOrder, NetworkEndian};
+    use std::time::SystemTime;
+
+    // mono packets of 2 samples, the first filled with 1s, the next with 2s and so on
+    fn numbered_packets(count: u8) -> Vec<Vec<u8>> {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        (1..=count)
+            .map(|i| rtp_out_stream.next_packet(&[i; 4]))
+            .collect()
+    }
 
     #[test]
     fn test_jitter() {
@@ -147,27 +739,645 @@ mod tests {
         let packet_2 = rtp_out_stream.next_packet(&test_data_2);
         let packet_3 = rtp_out_stream.next_packet(&test_data_3);
 
-        let mut rtp_in_jitter_stream = RtpJitterInStream::new(&packet_1);
-        let mut rtp_in_stream = RtpInStream::new(&packet_1);
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new(&packet_1).unwrap();
+        let mut rtp_in_stream = RtpInStream::new(&packet_1).unwrap();
 
-        rtp_in_jitter_stream.next_packet(&packet_3);
-        rtp_in_stream.next_packet(&packet_3);
+        rtp_in_jitter_stream.next_packet(&packet_3).unwrap();
+        rtp_in_stream.next_packet(&packet_3).unwrap();
 
-        rtp_in_jitter_stream.next_packet(&packet_2);
-        rtp_in_stream.next_packet(&packet_2);
+        rtp_in_jitter_stream.next_packet(&packet_2).unwrap();
+        rtp_in_stream.next_packet(&packet_2).unwrap();
 
         for i in 0..4 {
             // first packet was sent in order
             assert_eq!(rtp_in_stream.audio_slices[0].0[i], 1u8);
-            assert_eq!(rtp_in_jitter_stream.audio_slices[0].0[i], 1u8);
+            assert_eq!(rtp_in_jitter_stream.audio_slices[&0].0[i], 1u8);
 
             // second was sent out of order
             assert_eq!(rtp_in_stream.audio_slices[1].0[i], 3u8);
-            assert_eq!(rtp_in_jitter_stream.audio_slices[1].0[i], 2u8);
+            assert_eq!(rtp_in_jitter_stream.audio_slices[&1].0[i], 2u8);
 
             // expect the jitter receiver to have corrected it
             assert_eq!(rtp_in_stream.audio_slices[2].0[i], 2u8);
-            assert_eq!(rtp_in_jitter_stream.audio_slices[2].0[i], 3u8);
+            assert_eq!(rtp_in_jitter_stream.audio_slices[&2].0[i], 3u8);
+        }
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let packets = numbered_packets(4);
+
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new(&packets[0]).unwrap();
+        rtp_in_jitter_stream.next_packet(&packets[2]).unwrap();
+        rtp_in_jitter_stream.next_packet(&packets[0]).unwrap(); // the first one again
+        rtp_in_jitter_stream.next_packet(&packets[1]).unwrap();
+        rtp_in_jitter_stream.next_packet(&packets[2]).unwrap(); // out of order and duplicated
+        rtp_in_jitter_stream.next_packet(&packets[3]).unwrap();
+
+        let seqs = rtp_in_jitter_stream
+            .audio_slices
+            .values()
+            .map(|slice| (slice.0[0], slice.1))
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, vec![(1, 0), (2, 1), (3, 2), (4, 3)]);
+
+        // nothing to conceal either
+        rtp_in_jitter_stream.plc();
+        assert_eq!(rtp_in_jitter_stream.audio_slices.len(), 4);
+        assert_eq!(rtp_in_jitter_stream.duplicates(), 2);
+        assert_eq!(rtp_in_jitter_stream.concealed(), 0);
+    }
+
+    #[test]
+    fn test_plc() {
+        let packets = numbered_packets(6);
+
+        // 2, 3 and 5 are lost, 6 overtakes 4
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new(&packets[0]).unwrap();
+        // repeats, to tell where the concealment came from
+        rtp_in_jitter_stream.set_concealment(Concealment::Codec);
+        rtp_in_jitter_stream.next_packet(&packets[5]).unwrap();
+        rtp_in_jitter_stream.next_packet(&packets[3]).unwrap();
+
+        rtp_in_jitter_stream.plc();
+        let seqs = rtp_in_jitter_stream
+            .audio_slices
+            .values()
+            .map(|slice| (slice.0[0], slice.1, slice.2))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            seqs,
+            vec![
+                (1, 0, 0),
+                (1, 1, 2),
+                (1, 2, 4),
+                (4, 3, 6),
+                (4, 4, 8),
+                (6, 5, 10)
+            ]
+        );
+        assert_eq!(rtp_in_jitter_stream.concealed(), 3);
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut packets = numbered_packets(5);
+
+        // the sender restarts after the second packet, with new seq. numbers and timestamps
+        let (header, _) = RtpHeader::from_buf(&packets[1]).unwrap();
+        for (i, packet) in packets[2..].iter_mut().enumerate() {
+            NetworkEndian::write_u16(
+                &mut packet[2..],
+                header.sequence.wrapping_add(30000 + i as u16),
+            );
+            NetworkEndian::write_u32(&mut packet[4..], 1_000_000 + 2 * i as u32);
+        }
+
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new(&packets[0]).unwrap();
+        for packet in &packets[1..] {
+            rtp_in_jitter_stream.next_packet(packet).unwrap();
+        }
+
+        // the first packet after the jump is thrown away, the rest carry on where the old ones left off
+        let seqs = rtp_in_jitter_stream
+            .audio_slices
+            .values()
+            .map(|slice| (slice.0[0], slice.1, slice.2))
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, vec![(1, 0, 0), (2, 1, 2), (4, 2, 4), (5, 3, 6)]);
+    }
+
+    #[test]
+    fn test_interarrival_jitter() {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        let test_data = vec![0u8; 882]; // 10ms of mono
+
+        let t0 = Instant::now();
+        let first_packet = rtp_out_stream.next_packet(&test_data);
+
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&first_packet, t0).unwrap();
+        let mut rtp_in_stream = RtpInStream::new_at(&first_packet, t0).unwrap();
+
+        // every other packet shows up 5ms late
+        for i in 1..200u64 {
+            let packet = rtp_out_stream.next_packet(&test_data);
+            let arrival = t0 + Duration::from_millis(i * 10 + (i % 2) * 5);
+            rtp_in_jitter_stream
+                .next_packet_at(&packet, arrival)
+                .unwrap();
+            rtp_in_stream.next_packet_at(&packet, arrival).unwrap();
+        }
+
+        for jitter_ms in &[
+            rtp_in_jitter_stream.interarrival_jitter_ms(),
+            rtp_in_stream.interarrival_jitter_ms(),
+        ] {
+            assert!(*jitter_ms > 4.5 && *jitter_ms <= 5.0);
+        }
+        assert_eq!(
+            rtp_in_jitter_stream.interarrival_jitter(),
+            rtp_in_stream.interarrival_jitter()
+        );
+    }
+
+    #[test]
+    fn test_rtcp_reports() {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        let test_data = vec![0u8; 4];
+
+        let packets = (0..10)
+            .map(|_| rtp_out_stream.next_packet(&test_data))
+            .collect::<Vec<_>>();
+
+        let sr = match RtcpPacket::from_buf(&rtp_out_stream.sender_report(SystemTime::now())) {
+            Ok(RtcpPacket::SenderReport(sr)) => sr,
+            _ => panic!("expected a sender report"),
+        };
+        assert_eq!(sr.packet_count, 10);
+        assert_eq!(sr.octet_count, 40);
+
+        let t0 = Instant::now();
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&packets[0], t0).unwrap();
+
+        // 2 and 5 are lost
+        for (i, packet) in packets.iter().enumerate().skip(1) {
+            if i != 2 && i != 5 {
+                rtp_in_jitter_stream.next_packet_at(packet, t0).unwrap();
+            }
+        }
+
+        rtp_in_jitter_stream.receive_sender_report(&sr, t0).unwrap();
+
+        let rr = rtp_in_jitter_stream.receiver_report(0x1234, t0 + Duration::from_secs(1));
+        let rr = match RtcpPacket::from_buf(&rr) {
+            Ok(RtcpPacket::ReceiverReport(rr)) => rr,
+            _ => panic!("expected a receiver report"),
+        };
+
+        assert_eq!(rr.ssrc, 0x1234);
+        let block = &rr.reports[0];
+        assert_eq!(block.ssrc, sr.ssrc);
+        assert_eq!(block.fraction_lost, 51); // 2/10 * 256
+        assert_eq!(block.cumulative_lost, 2);
+        assert_eq!(
+            block.extended_highest_seq,
+            RtpHeader::from_buf(&packets[9]).unwrap().0.sequence() as u32
+        );
+        assert_eq!(block.last_sr, ntp_middle_bits(sr.ntp_timestamp));
+        assert_eq!(block.delay_since_last_sr, 65536);
+    }
+
+    #[test]
+    fn test_bye() {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        rtp_out_stream.set_cname("sender@127.0.0.1");
+
+        let test_data = vec![1u8, 1u8, 1u8, 1u8];
+        let packet = rtp_out_stream.next_packet(&test_data);
+
+        let t0 = Instant::now();
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&packet, t0).unwrap();
+        let mut rtp_in_stream = RtpInStream::new_at(&packet, t0).unwrap();
+
+        let bye = rtp_out_stream.bye(Some("end of stream"), SystemTime::now());
+
+        let packets = split_compound(&bye).unwrap();
+        assert_eq!(packets.len(), 3);
+        match packets[1] {
+            RtcpPacket::SourceDescription(ref sdes) => {
+                assert_eq!(
+                    sdes.cname(RtpHeader::from_buf(&packet).unwrap().0.ssrc()),
+                    Some("sender@127.0.0.1")
+                );
+            }
+            _ => panic!("expected our CNAME after the SR"),
+        }
+
+        assert!(!rtp_in_jitter_stream.ended());
+        rtp_in_jitter_stream.receive_rtcp(&bye, t0).unwrap();
+        rtp_in_stream.receive_rtcp(&bye).unwrap();
+        assert!(rtp_in_jitter_stream.ended());
+        assert!(rtp_in_stream.ended());
+
+        // the SR that came with the BYE was picked up too
+        assert_ne!(rtp_in_jitter_stream.report_block(t0).last_sr, 0);
+
+        // all that's left is playing out what's in the buffer
+        let much_later = t0 + Duration::from_secs(1);
+        assert!(rtp_in_jitter_stream.pop_frame(much_later).is_some());
+        assert!(rtp_in_jitter_stream.pop_frame(much_later).is_none());
+    }
+
+    #[test]
+    fn test_playout_before_first_arrival() {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        let packet = rtp_out_stream.next_packet(&[1u8; 4]);
+
+        let t0 = Instant::now();
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&packet, t0).unwrap();
+
+        // skipped so far ahead the playout time is further back than some platforms' Instant goes, it's due now
+        rtp_in_jitter_stream.skipped_ms = 1e22;
+        assert!(rtp_in_jitter_stream.playout_time(0) <= t0);
+        assert!(rtp_in_jitter_stream.pop_frame(t0).is_some());
+    }
+
+    #[test]
+    fn test_pop_frame() {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+
+        let test_data_1 = vec![1u8, 1u8, 1u8, 1u8];
+        let test_data_2 = vec![2u8, 2u8, 2u8, 2u8];
+        let test_data_3 = vec![3u8, 3u8, 3u8, 3u8];
+
+        let packet_1 = rtp_out_stream.next_packet(&test_data_1);
+        let _packet_2 = rtp_out_stream.next_packet(&test_data_2); // lost in the network
+        let packet_3 = rtp_out_stream.next_packet(&test_data_3);
+
+        let t0 = Instant::now();
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&packet_1, t0).unwrap();
+        // repeats, to tell where the concealment came from
+        rtp_in_jitter_stream.set_concealment(Concealment::Codec);
+
+        // nothing is played before the playout delay has passed
+        assert!(rtp_in_jitter_stream.pop_frame(t0).is_none());
+        assert_eq!(
+            rtp_in_jitter_stream.target_delay_ms(),
+            JITTERS_PLAYOUT_DELAY_MS as f64
+        );
+        assert_eq!(
+            rtp_in_jitter_stream.buffer_depth_ms(),
+            rtp_out_stream.format().samples_to_ms(test_data_1.len() / 2)
+        );
+
+        let later = t0 + Duration::from_millis(JITTERS_PLAYOUT_DELAY_MS);
+        let frame_1 = rtp_in_jitter_stream.pop_frame(later).unwrap();
+        assert_eq!(frame_1, (test_data_1.clone(), 0, 0));
+
+        // packet 3 arrives while packet 1 is playing
+        rtp_in_jitter_stream.next_packet(&packet_3).unwrap();
+
+        let much_later = later + Duration::from_secs(1);
+
+        // packet 2 never came - it gets concealed with a copy of packet 1
+        let frame_2 = rtp_in_jitter_stream.pop_frame(much_later).unwrap();
+        assert_eq!(frame_2, (test_data_1, 1, 2)); // 2 mono samples per packet
+
+        let frame_3 = rtp_in_jitter_stream.pop_frame(much_later).unwrap();
+        assert_eq!(frame_3, (test_data_3, 2, 4));
+        assert_eq!(rtp_in_jitter_stream.buffer_depth_ms(), 0.0);
+
+        assert_eq!(
+            rtp_in_jitter_stream.jitter_stats(),
+            "corrected 0 out-of-order packets, concealed 1 lost packets"
+        );
+    }
+
+    #[test]
+    fn test_pitch_concealment() {
+        // 10ms packets of a wave with a period of 64 samples, so a packet isn't a whole number of periods
+        let format = PayloadFormat::l16(96, 8000, 1).unwrap();
+        let mut rtp_out_stream = RtpOutStream::with_format(format.clone()).unwrap();
+        let wave = (0..800)
+            .map(|i| (8000.0 * (2.0 * std::f32::consts::PI * i as f32 / 64.0).sin()) as i16)
+            .collect::<Vec<_>>();
+        let packets = wave
+            .chunks(80)
+            .map(|pcm| rtp_out_stream.next_frame(pcm))
+            .collect::<Vec<_>>();
+
+        let t0 = Instant::now();
+        let mut live = RtpJitterInStream::with_format_at(&packets[0], format.clone(), t0).unwrap();
+        let mut batch = RtpJitterInStream::with_format_at(&packets[0], format, t0).unwrap();
+        for (i, packet) in packets.iter().enumerate().skip(1) {
+            if i != 6 {
+                live.next_packet_at(packet, t0).unwrap();
+                batch.next_packet_at(packet, t0).unwrap();
+            }
+        }
+
+        let much_later = t0 + Duration::from_secs(1);
+        let mut played = Vec::new();
+        while let Some((payload, _, _)) = live.pop_frame(much_later) {
+            played.extend(live.decode(&payload));
+            if played.len() == wave.len() {
+                break;
+            }
+        }
+        batch.plc();
+        let payloads = batch.audio_slices.values().cloned().collect::<Vec<_>>();
+        let batch_played = payloads
+            .iter()
+            .flat_map(|slice| batch.decode(&slice.0))
+            .collect::<Vec<_>>();
+        assert_eq!(played, batch_played);
+
+        // the lost packet carries on with the wave, a copy of the one before wouldn't line up
+        let err = played[480..560]
+            .iter()
+            .zip(wave[480..560].iter())
+            .map(|(a, b)| (i32::from(*a) - i32::from(*b)).abs())
+            .max()
+            .unwrap();
+        assert!(err < 800, "off by {}", err);
+        assert_eq!(played[..480], wave[..480]);
+    }
+
+    #[test]
+    fn test_gone_quiet() {
+        // 10ms packets of mono, and no marker bit at the end
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        let packets = (0..10u8)
+            .map(|i| rtp_out_stream.next_packet(&[i; 882]))
+            .collect::<Vec<_>>();
+
+        let t0 = Instant::now();
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&packets[0], t0).unwrap();
+        rtp_in_jitter_stream.set_concealment(Concealment::Codec);
+        rtp_in_jitter_stream.set_max_concealment(30.0);
+        rtp_in_jitter_stream
+            .next_packet_at(&packets[1], t0)
+            .unwrap();
+
+        let much_later = t0 + Duration::from_secs(1);
+        for i in 0..2u8 {
+            assert_eq!(rtp_in_jitter_stream.pop_frame(much_later).unwrap().0[0], i);
+        }
+
+        // the sender stops sending, 30ms are concealed and then there's nothing to play
+        for seq in 2..5 {
+            let frame = rtp_in_jitter_stream.pop_frame(much_later).unwrap();
+            assert_eq!((frame.0[0], frame.1), (1, seq));
+        }
+        assert!(rtp_in_jitter_stream.pop_frame(much_later).is_none());
+        assert!(!rtp_in_jitter_stream.ended());
+
+        // until it starts up again, playout picks up from there
+        rtp_in_jitter_stream
+            .next_packet_at(&packets[9], much_later)
+            .unwrap();
+        let frame = rtp_in_jitter_stream.pop_frame(much_later).unwrap();
+        assert_eq!((frame.0[0], frame.1, frame.2), (9, 9, 9 * 441));
+        assert_eq!(rtp_in_jitter_stream.concealed(), 3);
+    }
+
+    #[test]
+    fn test_late_packets() {
+        for &late_policy in &[
+            LatePacketPolicy::Discard,
+            LatePacketPolicy::RefineConcealment,
+        ] {
+            let packets = numbered_packets(4);
+
+            let t0 = Instant::now();
+            let much_later = t0 + Duration::from_secs(1);
+            let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&packets[0], t0).unwrap();
+            rtp_in_jitter_stream.set_concealment(Concealment::Codec);
+            rtp_in_jitter_stream.set_late_policy(late_policy);
+            rtp_in_jitter_stream.next_packet(&packets[3]).unwrap();
+
+            assert_eq!(
+                rtp_in_jitter_stream.pop_frame(much_later).unwrap().0,
+                [1; 4]
+            );
+            assert_eq!(
+                rtp_in_jitter_stream.pop_frame(much_later).unwrap().0,
+                [1; 4]
+            );
+
+            // packet 2 turns up after it's been concealed, it doesn't go back in the buffer
+            rtp_in_jitter_stream.next_packet(&packets[1]).unwrap();
+            assert_eq!(rtp_in_jitter_stream.audio_slices.len(), 1);
+
+            // packet 3 is concealed from whatever we know best
+            let frame_3 = rtp_in_jitter_stream.pop_frame(much_later).unwrap();
+            match late_policy {
+                LatePacketPolicy::Discard => assert_eq!(frame_3.0, [1; 4]),
+                LatePacketPolicy::RefineConcealment => assert_eq!(frame_3.0, [2; 4]),
+            }
+            assert_eq!(
+                rtp_in_jitter_stream.pop_frame(much_later).unwrap().0,
+                [4; 4]
+            );
+
+            // late isn't out of order
+            assert_eq!(rtp_in_jitter_stream.late(), 1);
+            assert_eq!(rtp_in_jitter_stream.concealed(), 2);
+            assert_eq!(
+                rtp_in_jitter_stream.jitter_stats(),
+                "corrected 0 out-of-order packets, concealed 2 lost packets, discarded 1 late packets"
+            );
+
+            // packet 1 again after it's been played is a duplicate, not late,
+            // and so is a second copy of the late packet
+            rtp_in_jitter_stream.next_packet(&packets[0]).unwrap();
+            rtp_in_jitter_stream.next_packet(&packets[1]).unwrap();
+            assert_eq!(rtp_in_jitter_stream.late(), 1);
+            assert_eq!(rtp_in_jitter_stream.duplicates(), 2);
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        for &overflow_policy in &[
+            OverflowPolicy::DropOldest,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::FlushAndResync,
+        ] {
+            let packets = numbered_packets(5);
+
+            let t0 = Instant::now();
+            let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&packets[0], t0).unwrap();
+            rtp_in_jitter_stream.set_max_depth(Some(BufferDepth::Packets(3)), overflow_policy);
+            rtp_in_jitter_stream.set_delay_bounds(100.0, 100.0).unwrap();
+            for packet in &packets[1..3] {
+                rtp_in_jitter_stream.next_packet_at(packet, t0).unwrap();
+            }
+
+            // the player stalled, and 2 more packets show up a second later
+            let t1 = t0 + Duration::from_secs(1);
+            for packet in &packets[3..] {
+                rtp_in_jitter_stream.next_packet_at(packet, t1).unwrap();
+            }
+            assert!(rtp_in_jitter_stream.audio_slices.len() <= 3);
+
+            let (expected, overflows): (&[u8], _) = match overflow_policy {
+                OverflowPolicy::DropOldest => (&[3, 4, 5], 2),
+                OverflowPolicy::DropNewest => (&[1, 2, 3], 2),
+                // starts over from packet 4, after the playout delay
+                OverflowPolicy::FlushAndResync => {
+                    assert!(rtp_in_jitter_stream.pop_frame(t1).is_none());
+                    (&[4, 5], 1)
+                }
+            };
+            let later = t1 + Duration::from_millis(200);
+            for i in expected {
+                assert_eq!(rtp_in_jitter_stream.pop_frame(later).unwrap().0, [*i; 4]);
+            }
+
+            assert_eq!(rtp_in_jitter_stream.overflowed(), overflows);
+        }
+    }
+
+    #[test]
+    fn test_max_depth_ms() {
+        // 10ms packets of mono
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        let packets = (0..10)
+            .map(|_| rtp_out_stream.next_packet(&[0u8; 882]))
+            .collect::<Vec<_>>();
+
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new(&packets[0]).unwrap();
+        rtp_in_jitter_stream.set_max_depth(Some(BufferDepth::Ms(50.0)), OverflowPolicy::DropOldest);
+        for packet in &packets[1..] {
+            rtp_in_jitter_stream.next_packet(packet).unwrap();
+        }
+        assert!(rtp_in_jitter_stream.buffer_depth_ms() <= 50.0);
+        assert_eq!(rtp_in_jitter_stream.audio_slices.keys().next(), Some(&5));
+    }
+
+    #[test]
+    fn test_dynamic_payload_type() {
+        let format = PayloadFormat::l16(96, 48000, 2).unwrap();
+        let mut rtp_out_stream = RtpOutStream::with_format(format.clone()).unwrap();
+        let test_data = vec![0u8; 960]; // 5ms of 48000 stereo
+
+        let t0 = Instant::now();
+        let first_packet = rtp_out_stream.next_packet(&test_data);
+
+        // no way to guess what 96 means
+        assert_eq!(
+            RtpJitterInStream::new_at(&first_packet, t0).err(),
+            Some(Error::UnsupportedPayloadType(96))
+        );
+        assert_eq!(
+            RtpJitterInStream::with_format_at(
+                &first_packet,
+                PayloadFormat::l16(97, 48000, 2).unwrap(),
+                t0
+            )
+            .err(),
+            Some(Error::PayloadTypeMismatch {
+                expected: 97,
+                got: 96
+            })
+        );
+
+        let mut rtp_in_jitter_stream =
+            RtpJitterInStream::with_format_at(&first_packet, format, t0).unwrap();
+        assert_eq!(rtp_in_jitter_stream.channels, 2);
+        assert_eq!(rtp_in_jitter_stream.format().clock_rate(), 48000);
+
+        for i in 1..10u64 {
+            let packet = rtp_out_stream.next_packet(&test_data);
+            rtp_in_jitter_stream
+                .next_packet_at(&packet, t0 + Duration::from_millis(i * 5))
+                .unwrap();
+        }
+        assert_eq!(rtp_in_jitter_stream.buffer_depth_ms(), 50.0);
+        assert_eq!(rtp_in_jitter_stream.interarrival_jitter(), 0);
+    }
+
+    #[test]
+    fn test_g711() {
+        let mut rtp_out_stream =
+            RtpOutStream::with_format(PayloadFormat::from_static(0).unwrap()).unwrap();
+        let tone: Vec<i16> = (0..160).map(|i| ((i % 16) as i16 - 8) * 1000).collect();
+        let test_data = encode_ulaw(&tone); // 20ms
+
+        let t0 = Instant::now();
+        let first_packet = rtp_out_stream.next_packet(&test_data);
+        let second_packet = rtp_out_stream.next_packet(&test_data);
+
+        // the static payload types are recognized without being told
+        let mut rtp_in_jitter_stream = RtpJitterInStream::new_at(&first_packet, t0).unwrap();
+        assert_eq!(rtp_in_jitter_stream.format().encoding_name(), "PCMU");
+        rtp_in_jitter_stream
+            .next_packet_at(&second_packet, t0 + Duration::from_millis(20))
+            .unwrap();
+
+        // one byte per sample, so the timestamp goes up by 160 a packet
+        assert_eq!(rtp_in_jitter_stream.audio_slices[&1].2, 160);
+        assert_eq!(rtp_in_jitter_stream.buffer_depth_ms(), 40.0);
+        assert_eq!(rtp_in_jitter_stream.interarrival_jitter(), 0);
+
+        let decoded = decode_ulaw(&rtp_in_jitter_stream.audio_slices[&1].0);
+        for (sample, decoded) in tone.iter().zip(decoded.iter()) {
+            assert!((sample - decoded).abs() < 128);
+        }
+    }
+
+    #[test]
+    fn test_l24_plc() {
+        let format = PayloadFormat::from_rtpmap(97, "L24/48000/1").unwrap();
+        let mut rtp_out_stream = RtpOutStream::with_format(format.clone()).unwrap();
+        let pcm: Vec<i32> = (0..48).map(|i| (i - 24) * 0x1_0001).collect(); // 1ms, low bits set
+        let packets = (0..3)
+            .map(|_| rtp_out_stream.next_packet(&L24::pack(&pcm)))
+            .collect::<Vec<_>>();
+
+        // the second one is lost, the audio around the gap keeps all 24 bits
+        let mut rtp_in_jitter_stream = RtpJitterInStream::with_format(&packets[0], format).unwrap();
+        rtp_in_jitter_stream.next_packet(&packets[2]).unwrap();
+        rtp_in_jitter_stream.plc();
+        for slice in rtp_in_jitter_stream.audio_slices.values() {
+            assert_eq!(L24::unpack(&slice.0), pcm);
+        }
+    }
+
+    // L16 that conceals losses with silence instead of a repeat
+    struct SilentL16(L16);
+
+    impl Codec for SilentL16 {
+        fn format(&self) -> &PayloadFormat {
+            self.0.format()
+        }
+
+        fn encode(&mut self, pcm: &[i16]) -> Vec<u8> {
+            self.0.encode(pcm)
+        }
+
+        fn decode(&mut self, payload: &[u8]) -> Vec<i16> {
+            self.0.decode(payload)
+        }
+
+        fn payload_samples(&self, payload: &[u8]) -> usize {
+            self.0.payload_samples(payload)
+        }
+
+        fn conceal(&mut self, last_payload: &[u8]) -> Vec<u8> {
+            vec![0u8; last_payload.len()]
+        }
+    }
+
+    #[test]
+    fn test_codec() {
+        let format = PayloadFormat::l16(96, 8000, 1).unwrap();
+        let mut rtp_out_stream = RtpOutStream::with_codec(L16::new(format.clone()).unwrap());
+
+        let packet_1 = rtp_out_stream.next_frame(&[1000i16, -1000i16]);
+        let _packet_2 = rtp_out_stream.next_frame(&[2000i16, -2000i16]); // lost in the network
+        let packet_3 = rtp_out_stream.next_frame(&[3000i16, -3000i16]);
+
+        let t0 = Instant::now();
+        let mut rtp_in_jitter_stream =
+            RtpJitterInStream::with_codec_at(&packet_1, SilentL16(L16::new(format).unwrap()), t0)
+                .unwrap();
+        rtp_in_jitter_stream.next_packet_at(&packet_3, t0).unwrap();
+
+        let much_later = t0 + Duration::from_secs(1);
+        let mut played = Vec::new();
+        for _ in 0..3 {
+            let (payload, _, _) = rtp_in_jitter_stream.pop_frame(much_later).unwrap();
+            played.push(rtp_in_jitter_stream.decode(&payload));
         }
+        assert_eq!(
+            played,
+            vec![
+                vec![1000i16, -1000i16],
+                vec![0i16, 0i16],
+                vec![3000i16, -3000i16]
+            ]
+        );
     }
 }
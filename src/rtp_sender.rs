@@ -0,0 +1,282 @@
+use crate::{
+    codec::{BoxedCodec, Codec},
+    error::Error,
+    rtcp::{split_compound, RtcpPacket},
+    rtp::{RtpHeader, RtpOutStream},
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+/* the sending side of a session, our RtpOutStream plus what we hear from everyone else
+ * every SSRC we hear about is tied to the transport address it came from, so when the same SSRC shows
+ * up from somewhere else it's either two sources that picked the same one, or packets looping back
+ * when it's our own SSRC, the first time from an address is a collision and we move to a new SSRC,
+ * after that the address is on the conflict list and it's our own packets coming back to us
+ * see: https://tools.ietf.org/html/rfc3550#section-8.2
+ */
+
+// conflicting addresses are forgotten after 10 RTCP intervals of at least 5 seconds
+pub const JITTERS_CONFLICT_TIMEOUT_MS: u64 = 50_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SsrcCheck {
+    Ok,
+    // someone else has our SSRC, send the BYE for the old one, we've already moved to a new SSRC
+    Collision { old_ssrc: u32, bye: Vec<u8> },
+    // our own packets came back to us, drop it
+    Loop,
+    // two other sources clash (or loop), drop it
+    ThirdParty,
+}
+
+#[derive(Default)]
+struct SourceAddrs {
+    data: Option<SocketAddr>,
+    control: Option<SocketAddr>,
+}
+
+pub struct RtpSenderSession<C: Codec = BoxedCodec> {
+    rtp_stream: RtpOutStream<C>,
+    sources: HashMap<u32, SourceAddrs>,
+    conflicts: HashMap<SocketAddr, Instant>, // addresses that have sent our own SSRC, and when
+    collisions: u64,
+    loops: u64,
+    third_party: u64,
+}
+
+impl<C: Codec> RtpSenderSession<C> {
+    pub fn new(rtp_stream: RtpOutStream<C>) -> Self {
+        RtpSenderSession {
+            rtp_stream,
+            sources: HashMap::new(),
+            conflicts: HashMap::new(),
+            collisions: 0,
+            loops: 0,
+            third_party: 0,
+        }
+    }
+
+    // the out-stream, to send with
+    pub fn rtp_stream(&mut self) -> &mut RtpOutStream<C> {
+        &mut self.rtp_stream
+    }
+
+    // an RTP packet that came in from someone on the session
+    pub fn check_rtp(
+        &mut self,
+        packet: &[u8],
+        from: SocketAddr,
+        now: Instant,
+    ) -> Result<SsrcCheck, Error> {
+        let (header, _) = RtpHeader::from_buf(packet)?;
+        Ok(self.check_ssrc(header.ssrc(), from, false, now))
+    }
+
+    // an RTCP compound packet, every SSRC that speaks for itself in it is checked
+    pub fn check_rtcp(
+        &mut self,
+        buf: &[u8],
+        from: SocketAddr,
+        now: Instant,
+    ) -> Result<SsrcCheck, Error> {
+        let mut ssrcs = Vec::new();
+        for packet in split_compound(buf)? {
+            match packet {
+                RtcpPacket::SenderReport(sr) => ssrcs.push(sr.ssrc),
+                RtcpPacket::ReceiverReport(rr) => ssrcs.push(rr.ssrc),
+                RtcpPacket::SourceDescription(sdes) => {
+                    ssrcs.extend(sdes.chunks.iter().map(|chunk| chunk.ssrc))
+                }
+                RtcpPacket::Bye(bye) => ssrcs.extend(bye.ssrcs),
+                RtcpPacket::App(app) => ssrcs.push(app.ssrc),
+            }
+        }
+        ssrcs.dedup();
+
+        for ssrc in ssrcs {
+            let check = self.check_ssrc(ssrc, from, true, now);
+            if check != SsrcCheck::Ok {
+                return Ok(check);
+            }
+        }
+        Ok(SsrcCheck::Ok)
+    }
+
+    fn check_ssrc(
+        &mut self,
+        ssrc: u32,
+        from: SocketAddr,
+        control: bool,
+        now: Instant,
+    ) -> SsrcCheck {
+        self.conflicts.retain(|_, seen| {
+            now.saturating_duration_since(*seen)
+                < Duration::from_millis(JITTERS_CONFLICT_TIMEOUT_MS)
+        });
+
+        if ssrc == self.rtp_stream.ssrc() {
+            // we never hear our own packets, so any address is a conflicting one
+            if let Some(seen) = self.conflicts.get_mut(&from) {
+                *seen = now;
+                self.loops += 1;
+                return SsrcCheck::Loop;
+            }
+
+            self.collisions += 1;
+            self.conflicts.insert(from, now);
+            let in_use = self.sources.keys().cloned().collect::<Vec<_>>();
+            let bye = self.rtp_stream.change_ssrc(&in_use, SystemTime::now());
+
+            // the old SSRC belongs to whoever we collided with now
+            let mut addrs = SourceAddrs::default();
+            if control {
+                addrs.control = Some(from);
+            } else {
+                addrs.data = Some(from);
+            }
+            self.sources.insert(ssrc, addrs);
+
+            return SsrcCheck::Collision {
+                old_ssrc: ssrc,
+                bye,
+            };
+        }
+
+        let addrs = self.sources.entry(ssrc).or_default();
+        let addr = if control {
+            &mut addrs.control
+        } else {
+            &mut addrs.data
+        };
+        match addr {
+            // the first data packet or the first control packet for this source
+            None => {
+                *addr = Some(from);
+                SsrcCheck::Ok
+            }
+            Some(addr) if *addr == from => SsrcCheck::Ok,
+            Some(_) => {
+                self.third_party += 1;
+                SsrcCheck::ThirdParty
+            }
+        }
+    }
+
+    // a source said BYE or timed out, its SSRC is up for grabs again
+    pub fn forget_source(&mut self, ssrc: u32) {
+        self.sources.remove(&ssrc);
+    }
+
+    pub fn collisions(&self) -> u64 {
+        self.collisions
+    }
+
+    pub fn loops(&self) -> u64 {
+        self.loops
+    }
+
+    pub fn third_party_conflicts(&self) -> u64 {
+        self.third_party
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([192, 168, 0, 2], port))
+    }
+
+    #[test]
+    fn test_collision() {
+        let mut session = RtpSenderSession::new(RtpOutStream::new(1));
+        let old_ssrc = session.rtp_stream().ssrc();
+        let ours = session.rtp_stream().next_packet(&[0u8; 4]);
+        let now = Instant::now();
+
+        // someone else sent a packet with our SSRC
+        match session.check_rtp(&ours, addr(5004), now).unwrap() {
+            SsrcCheck::Collision {
+                old_ssrc: ssrc,
+                bye,
+            } => {
+                assert_eq!(ssrc, old_ssrc);
+                match &split_compound(&bye).unwrap()[2] {
+                    RtcpPacket::Bye(bye) => assert_eq!(bye.ssrcs, vec![old_ssrc]),
+                    packet => panic!("expected a BYE, got {:?}", packet),
+                }
+            }
+            check => panic!("expected a collision, got {:?}", check),
+        }
+        let new_ssrc = session.rtp_stream().ssrc();
+        assert_ne!(new_ssrc, old_ssrc);
+        assert_eq!(session.collisions(), 1);
+
+        // the old SSRC is theirs now
+        assert_eq!(
+            session.check_rtp(&ours, addr(5004), now).unwrap(),
+            SsrcCheck::Ok
+        );
+
+        // the new one keeps going
+        let packet = session.rtp_stream().next_packet(&[0u8; 4]);
+        let header = RtpHeader::from_buf(&packet).unwrap().0;
+        assert_eq!(header.ssrc(), new_ssrc);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut session = RtpSenderSession::new(RtpOutStream::new(1));
+        let first = session.rtp_stream().next_packet(&[0u8; 4]);
+        let now = Instant::now();
+
+        let check = session.check_rtp(&first, addr(5004), now).unwrap();
+        assert!(matches!(check, SsrcCheck::Collision { .. }));
+
+        // our packets under the new SSRC come back from the same place, it's a loop
+        let second = session.rtp_stream().next_packet(&[0u8; 4]);
+        let ssrc = session.rtp_stream().ssrc();
+        assert_eq!(
+            session.check_rtp(&second, addr(5004), now).unwrap(),
+            SsrcCheck::Loop
+        );
+        assert_eq!(session.rtp_stream().ssrc(), ssrc);
+        assert_eq!(session.loops(), 1);
+
+        // until we've forgotten about the conflict
+        let much_later = now + Duration::from_millis(JITTERS_CONFLICT_TIMEOUT_MS);
+        let check = session.check_rtp(&second, addr(5004), much_later).unwrap();
+        assert!(matches!(check, SsrcCheck::Collision { .. }));
+    }
+
+    #[test]
+    fn test_third_party() {
+        let mut session = RtpSenderSession::new(RtpOutStream::new(1));
+        let mut other = RtpOutStream::new(1);
+        let packet = other.next_packet(&[0u8; 4]);
+        let sr = other.sender_report(SystemTime::now());
+        let now = Instant::now();
+
+        // data and control come from different ports
+        assert_eq!(
+            session.check_rtp(&packet, addr(5004), now).unwrap(),
+            SsrcCheck::Ok
+        );
+        assert_eq!(
+            session.check_rtcp(&sr, addr(5005), now).unwrap(),
+            SsrcCheck::Ok
+        );
+
+        // the same SSRC from somewhere else
+        assert_eq!(
+            session.check_rtp(&packet, addr(6004), now).unwrap(),
+            SsrcCheck::ThirdParty
+        );
+        assert_eq!(session.third_party_conflicts(), 1);
+        assert_eq!(session.collisions(), 0);
+    }
+}
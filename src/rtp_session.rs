@@ -0,0 +1,461 @@
+use crate::{
+    error::Error,
+    payload::PayloadFormat,
+    rtcp::{split_compound, ReceiverReport, RtcpPacket, RTCP_MAX_COUNT},
+    rtp::RtpHeader,
+    rtp_jitter::RtpJitterInStream,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/* a receiver for everything that arrives on one port, e.g. a conference with several participants
+ * each SSRC gets its own RtpJitterInStream, once it's made it through probation - MIN_SEQUENTIAL packets
+ * in sequence, so a stray packet or two doesn't become a participant
+ * see: https://tools.ietf.org/html/rfc3550#section-6.2.1
+ *      https://tools.ietf.org/html/rfc3550#appendix-A.1
+ */
+pub use crate::rtp_source::MIN_SEQUENTIAL;
+
+// 5 RTCP intervals of at least 5 seconds without hearing from a source and it's gone
+// see: https://tools.ietf.org/html/rfc3550#section-6.3.5
+pub const JITTERS_SOURCE_TIMEOUT_MS: u64 = 25_000;
+
+// SSRCs we're holding packets for before they've made it through probation, when a new one turns up
+// with this many already waiting the one we heard from least recently makes room for it
+pub const JITTERS_MAX_PROBATION: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    NewSource(u32),
+    SourceLeft(u32), // sent a BYE, its stream is ended but still there to be played out
+    SourceTimedOut(u32),
+}
+
+struct Probation {
+    max_seq: u16,
+    probation: u16,
+    packets: Vec<(Vec<u8>, Instant)>, // held on to until the source is valid
+}
+
+struct SessionSource {
+    stream: RtpJitterInStream,
+    last_heard: Instant,
+}
+
+pub struct RtpSession {
+    format: Option<PayloadFormat>,
+    sources: HashMap<u32, SessionSource>,
+    probation: HashMap<u32, Probation>,
+    events: VecDeque<SessionEvent>,
+    source_timeout: Duration,
+    next_report: usize, // where the next RR starts, when there are too many sources for one
+}
+
+impl RtpSession {
+    // only the static payload types can be worked out from the packets
+    pub fn new() -> Self {
+        RtpSession {
+            format: None,
+            sources: HashMap::new(),
+            probation: HashMap::new(),
+            events: VecDeque::new(),
+            source_timeout: Duration::from_millis(JITTERS_SOURCE_TIMEOUT_MS),
+            next_report: 0,
+        }
+    }
+
+    // every source sends this format, e.g. L16/48000/2 under a dynamic payload type
+    pub fn with_format(format: PayloadFormat) -> Self {
+        RtpSession {
+            format: Some(format),
+            ..RtpSession::new()
+        }
+    }
+
+    pub fn set_source_timeout(&mut self, source_timeout: Duration) {
+        self.source_timeout = source_timeout;
+    }
+
+    pub fn next_packet(&mut self, packet: &[u8]) -> Result<(), Error> {
+        self.next_packet_at(packet, Instant::now())
+    }
+
+    pub fn next_packet_at(&mut self, packet: &[u8], arrival: Instant) -> Result<(), Error> {
+        let (header, _) = RtpHeader::from_buf(packet)?;
+        let ssrc = header.ssrc();
+
+        if let Some(source) = self.sources.get_mut(&ssrc) {
+            source.last_heard = arrival;
+            return source.stream.next_packet_at(packet, arrival);
+        }
+
+        if !self.probation.contains_key(&ssrc) && self.probation.len() >= JITTERS_MAX_PROBATION {
+            let stalest = self
+                .probation
+                .iter()
+                .min_by_key(|(_, probation)| probation.packets.last().map(|(_, arrival)| *arrival))
+                .map(|(ssrc, _)| *ssrc);
+            if let Some(stalest) = stalest {
+                self.probation.remove(&stalest);
+            }
+        }
+
+        let seq = header.sequence();
+        let probation = self.probation.entry(ssrc).or_insert(Probation {
+            max_seq: seq.wrapping_sub(1),
+            probation: MIN_SEQUENTIAL,
+            packets: Vec::new(),
+        });
+
+        if seq == probation.max_seq.wrapping_add(1) {
+            probation.probation -= 1;
+        } else {
+            // out of sequence, start over with this one
+            probation.probation = MIN_SEQUENTIAL - 1;
+            probation.packets.clear();
+        }
+        probation.max_seq = seq;
+        probation.packets.push((packet.to_vec(), arrival));
+
+        if probation.probation > 0 {
+            return Ok(());
+        }
+
+        let probation = self.probation.remove(&ssrc).unwrap();
+        let (first_packet, first_arrival) = &probation.packets[0];
+        let mut stream = match self.format {
+            Some(ref format) => {
+                RtpJitterInStream::with_format_at(first_packet, format.clone(), *first_arrival)?
+            }
+            None => RtpJitterInStream::new_at(first_packet, *first_arrival)?,
+        };
+        // a bad packet among them only costs that packet, not the source
+        for (packet, arrival) in &probation.packets[1..] {
+            stream.next_packet_at(packet, *arrival).ok();
+        }
+
+        self.sources.insert(
+            ssrc,
+            SessionSource {
+                stream,
+                last_heard: arrival,
+            },
+        );
+        self.events.push_back(SessionEvent::NewSource(ssrc));
+        Ok(())
+    }
+
+    // SRs go to their source, and a BYE ends each of the sources it names
+    pub fn receive_rtcp(&mut self, buf: &[u8], arrival: Instant) -> Result<(), Error> {
+        for packet in split_compound(buf)? {
+            match packet {
+                RtcpPacket::SenderReport(ref sr) => {
+                    if let Some(source) = self.sources.get_mut(&sr.ssrc) {
+                        source.last_heard = arrival;
+                        source.stream.receive_sender_report(sr, arrival)?;
+                    }
+                }
+                RtcpPacket::Bye(ref bye) => {
+                    for ssrc in &bye.ssrcs {
+                        self.probation.remove(ssrc);
+                        // the BYE might come more than once, the source only leaves the first time
+                        if let Some(source) = self.sources.get_mut(ssrc) {
+                            if !source.stream.ended() {
+                                source.stream.end();
+                                self.events.push_back(SessionEvent::SourceLeft(*ssrc));
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // drops the sources we haven't heard from in a while, call it every so often
+    pub fn expire(&mut self, now: Instant) {
+        let source_timeout = self.source_timeout;
+        let timed_out = self
+            .sources
+            .iter()
+            .filter(|(_, source)| now.saturating_duration_since(source.last_heard) > source_timeout)
+            .map(|(ssrc, _)| *ssrc)
+            .collect::<Vec<_>>();
+
+        for ssrc in timed_out {
+            self.sources.remove(&ssrc);
+            self.events.push_back(SessionEvent::SourceTimedOut(ssrc));
+        }
+
+        self.probation
+            .retain(|_, probation| match probation.packets.last() {
+                Some((_, arrival)) => now.saturating_duration_since(*arrival) <= source_timeout,
+                None => false,
+            });
+    }
+
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn ssrcs(&self) -> Vec<u32> {
+        self.sources.keys().cloned().collect()
+    }
+
+    pub fn source(&mut self, ssrc: u32) -> Option<&mut RtpJitterInStream> {
+        self.sources.get_mut(&ssrc).map(|source| &mut source.stream)
+    }
+
+    pub fn sources(&mut self) -> impl Iterator<Item = (&u32, &mut RtpJitterInStream)> {
+        self.sources
+            .iter_mut()
+            .map(|(ssrc, source)| (ssrc, &mut source.stream))
+    }
+
+    // e.g. once a source that said BYE has been played out
+    pub fn remove_source(&mut self, ssrc: u32) -> Option<RtpJitterInStream> {
+        self.sources.remove(&ssrc).map(|source| source.stream)
+    }
+
+    // RTCP RR from us about every source, up to the 31 a single RR can hold
+    // with more sources than that, each RR carries on from where the last one stopped
+    // see: https://tools.ietf.org/html/rfc3550#section-6.4
+    pub fn receiver_report(&mut self, ssrc: u32, now: Instant) -> Vec<u8> {
+        let mut ssrcs = self.ssrcs();
+        ssrcs.sort_unstable();
+        let start = match ssrcs.len() {
+            0 => 0,
+            len => self.next_report % len,
+        };
+        let count = ssrcs.len().min(RTCP_MAX_COUNT);
+        self.next_report = start + count;
+
+        let sources = &mut self.sources;
+        ReceiverReport {
+            ssrc,
+            reports: ssrcs
+                .iter()
+                .cycle()
+                .skip(start)
+                .take(count)
+                .filter_map(|ssrc| {
+                    sources
+                        .get_mut(ssrc)
+                        .map(|source| source.stream.report_block(now))
+                })
+                .collect(),
+        }
+        .to_buf()
+    }
+}
+
+impl Default for RtpSession {
+    fn default() -> Self {
+        RtpSession::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtp::RtpOutStream;
+    use std::time::SystemTime;
+
+    fn ssrc_of(packet: &[u8]) -> u32 {
+        RtpHeader::from_buf(packet).unwrap().0.ssrc()
+    }
+
+    #[test]
+    fn test_probation() {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        let packets = (0..4)
+            .map(|_| rtp_out_stream.next_packet(&[0u8; 4]))
+            .collect::<Vec<_>>();
+        let ssrc = ssrc_of(&packets[0]);
+
+        let mut session = RtpSession::new();
+
+        // a single packet isn't enough, and neither is one out of sequence
+        session.next_packet(&packets[0]).unwrap();
+        assert!(session.ssrcs().is_empty());
+        session.next_packet(&packets[2]).unwrap();
+        assert!(session.ssrcs().is_empty());
+        assert_eq!(session.poll_event(), None);
+
+        session.next_packet(&packets[3]).unwrap();
+        assert_eq!(session.ssrcs(), vec![ssrc]);
+        assert_eq!(session.poll_event(), Some(SessionEvent::NewSource(ssrc)));
+        assert_eq!(session.poll_event(), None);
+
+        // the packets from probation made it into the stream
+        assert_eq!(session.source(ssrc).unwrap().audio_slices.len(), 2);
+    }
+
+    #[test]
+    fn test_demux() {
+        let mut alice = RtpOutStream::new(1);
+        let mut bob = RtpOutStream::new(1);
+        let mut session = RtpSession::new();
+
+        for _ in 0..3 {
+            session.next_packet(&alice.next_packet(&[1u8; 4])).unwrap();
+            session.next_packet(&bob.next_packet(&[2u8; 4])).unwrap();
+        }
+
+        let alice_ssrc = ssrc_of(&alice.next_packet(&[1u8; 4]));
+        let bob_ssrc = ssrc_of(&bob.next_packet(&[2u8; 4]));
+
+        let mut ssrcs = session.ssrcs();
+        ssrcs.sort();
+        let mut expected = vec![alice_ssrc, bob_ssrc];
+        expected.sort();
+        assert_eq!(ssrcs, expected);
+
+        for (ssrc, stream) in session.sources() {
+            let fill = if *ssrc == alice_ssrc { 1u8 } else { 2u8 };
+            assert_eq!(stream.audio_slices.len(), 3);
+            assert!(stream
+                .audio_slices
+                .values()
+                .all(|slice| slice.0 == vec![fill; 4]));
+        }
+
+        let rr = session.receiver_report(0x1234, Instant::now());
+        match &split_compound(&rr).unwrap()[0] {
+            RtcpPacket::ReceiverReport(rr) => assert_eq!(rr.reports.len(), 2),
+            packet => panic!("expected an RR, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_departures() {
+        let mut alice = RtpOutStream::new(1);
+        let mut bob = RtpOutStream::new(1);
+        let mut session = RtpSession::new();
+
+        let t0 = Instant::now();
+        for _ in 0..2 {
+            session
+                .next_packet_at(&alice.next_packet(&[1u8; 4]), t0)
+                .unwrap();
+            session
+                .next_packet_at(&bob.next_packet(&[2u8; 4]), t0)
+                .unwrap();
+        }
+        let alice_ssrc = ssrc_of(&alice.next_packet(&[1u8; 4]));
+        let bob_ssrc = ssrc_of(&bob.next_packet(&[2u8; 4]));
+        while session.poll_event().is_some() {}
+
+        // alice says goodbye, her stream sticks around until it's played out
+        session
+            .receive_rtcp(&alice.bye(None, SystemTime::now()), t0)
+            .unwrap();
+        assert_eq!(
+            session.poll_event(),
+            Some(SessionEvent::SourceLeft(alice_ssrc))
+        );
+        assert!(session.source(alice_ssrc).unwrap().ended());
+        assert!(session.remove_source(alice_ssrc).is_some());
+
+        // bob goes quiet
+        session.expire(t0 + Duration::from_millis(JITTERS_SOURCE_TIMEOUT_MS));
+        assert_eq!(session.ssrcs(), vec![bob_ssrc]);
+        session.expire(t0 + Duration::from_millis(JITTERS_SOURCE_TIMEOUT_MS + 1));
+        assert_eq!(
+            session.poll_event(),
+            Some(SessionEvent::SourceTimedOut(bob_ssrc))
+        );
+        assert!(session.ssrcs().is_empty());
+    }
+
+    #[test]
+    fn test_probation_bad_packet() {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        let first = rtp_out_stream.next_packet(&[1u8; 4]);
+        let mut second = rtp_out_stream.next_packet(&[2u8; 4]);
+        let ssrc = ssrc_of(&first);
+
+        // the packet that ends probation has the wrong payload type, the source still makes it
+        second[1] = (second[1] & 0x80) | 10; // keep the marker bit
+        let mut session = RtpSession::new();
+        session.next_packet(&first).unwrap();
+        session.next_packet(&second).unwrap();
+        assert_eq!(session.ssrcs(), vec![ssrc]);
+        assert_eq!(session.poll_event(), Some(SessionEvent::NewSource(ssrc)));
+        assert_eq!(session.source(ssrc).unwrap().audio_slices.len(), 1);
+    }
+
+    #[test]
+    fn test_repeated_bye() {
+        let mut rtp_out_stream = RtpOutStream::new(1);
+        let mut session = RtpSession::new();
+        for _ in 0..2 {
+            session
+                .next_packet(&rtp_out_stream.next_packet(&[0u8; 4]))
+                .unwrap();
+        }
+        let ssrc = rtp_out_stream.ssrc();
+        while session.poll_event().is_some() {}
+
+        let bye = rtp_out_stream.bye(None, SystemTime::now());
+        session.receive_rtcp(&bye, Instant::now()).unwrap();
+        session.receive_rtcp(&bye, Instant::now()).unwrap();
+        assert_eq!(session.poll_event(), Some(SessionEvent::SourceLeft(ssrc)));
+        assert_eq!(session.poll_event(), None);
+    }
+
+    #[test]
+    fn test_probation_limit() {
+        let mut streams = (0..=JITTERS_MAX_PROBATION)
+            .map(|_| RtpOutStream::new(1))
+            .collect::<Vec<_>>();
+        let mut session = RtpSession::new();
+
+        // one packet each, the first one to arrive is the one that makes room for the last
+        let t0 = Instant::now();
+        for (i, stream) in streams.iter_mut().enumerate() {
+            let arrival = t0 + Duration::from_millis(i as u64);
+            session
+                .next_packet_at(&stream.next_packet(&[0u8; 4]), arrival)
+                .unwrap();
+        }
+        assert_eq!(session.probation.len(), JITTERS_MAX_PROBATION);
+
+        let first = streams[0].next_packet(&[0u8; 4]);
+        assert!(!session.probation.contains_key(&ssrc_of(&first)));
+        let last = streams[JITTERS_MAX_PROBATION].next_packet(&[0u8; 4]);
+        session.next_packet(&last).unwrap();
+        assert_eq!(session.ssrcs(), vec![ssrc_of(&last)]);
+    }
+
+    #[test]
+    fn test_report_rotation() {
+        let mut streams = (0..40).map(|_| RtpOutStream::new(1)).collect::<Vec<_>>();
+        let mut session = RtpSession::new();
+        for stream in streams.iter_mut() {
+            for _ in 0..2 {
+                session.next_packet(&stream.next_packet(&[0u8; 4])).unwrap();
+            }
+        }
+        assert_eq!(session.ssrcs().len(), 40);
+
+        // too many for one RR, the next one picks up the ones the first left out
+        let mut reported = Vec::new();
+        for _ in 0..2 {
+            let rr = session.receiver_report(0x1234, Instant::now());
+            match &split_compound(&rr).unwrap()[0] {
+                RtcpPacket::ReceiverReport(rr) => {
+                    assert_eq!(rr.reports.len(), 31);
+                    reported.extend(rr.reports.iter().map(|report| report.ssrc));
+                }
+                packet => panic!("expected an RR, got {:?}", packet),
+            }
+        }
+        reported.sort_unstable();
+        reported.dedup();
+        assert_eq!(reported.len(), 40);
+    }
+}
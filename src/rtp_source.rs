@@ -0,0 +1,307 @@
+/* per-source reception state, after the `source` struct in RFC 3550 Appendix A
+ * see: https://tools.ietf.org/html/rfc3550#appendix-A.1
+ *      https://tools.ietf.org/html/rfc3550#appendix-A.3
+ *      https://tools.ietf.org/html/rfc3550#appendix-A.8
+ */
+pub const RTP_SEQ_MOD: u32 = 1 << 16;
+pub const MIN_SEQUENTIAL: u16 = 2;
+const MAX_DROPOUT: u16 = 3000;
+const MAX_MISORDER: u16 = 100;
+
+#[derive(Debug)]
+pub struct RtpSource {
+    max_seq: u16,         // highest seq. number seen
+    cycles: u32,          // shifted count of seq. number cycles
+    base_seq: u32,        // base seq number
+    bad_seq: u32,         // last 'bad' seq number + 1
+    received: u32,        // packets received
+    expected_prior: u32,  // packet expected at last interval
+    received_prior: u32,  // packet received at last interval
+    transit: Option<u32>, // relative transit time of the previous packet
+    jitter: u32,          // estimated jitter, scaled by 16 like the RFC's integer version
+    probation: u16,       // sequ. packets till source is valid
+    resyncs: u32,         // times we started over from a new seq. number
+}
+
+impl RtpSource {
+    // the first packet of the stream counts towards probation, see update_seq
+    pub fn new(seq: u16) -> Self {
+        let mut source = RtpSource {
+            max_seq: seq,
+            cycles: 0,
+            base_seq: u32::from(seq),
+            bad_seq: RTP_SEQ_MOD + 1,
+            received: 0,
+            expected_prior: 0,
+            received_prior: 0,
+            transit: None,
+            jitter: 0,
+            probation: MIN_SEQUENTIAL - 1,
+            resyncs: 0,
+        };
+        source.init_seq(seq);
+        source.received += 1;
+        source
+    }
+
+    fn init_seq(&mut self, seq: u16) {
+        self.base_seq = u32::from(seq);
+        self.max_seq = seq;
+        self.bad_seq = RTP_SEQ_MOD + 1; // so seq == bad_seq is false
+        self.cycles = 0;
+        self.received = 0;
+        self.received_prior = 0;
+        self.expected_prior = 0;
+    }
+
+    // the sender restarted, or what we started from was a stray, pretend this is the first packet
+    fn resync(&mut self, seq: u16) {
+        self.init_seq(seq);
+        self.resyncs += 1;
+    }
+
+    // returns false if the packet should be discarded
+    pub fn update_seq(&mut self, seq: u16) -> bool {
+        let udelta = seq.wrapping_sub(self.max_seq);
+        let in_window =
+            udelta < MAX_DROPOUT || udelta > (RTP_SEQ_MOD - u32::from(MAX_MISORDER)) as u16;
+
+        // the source isn't valid until MIN_SEQUENTIAL packets in a row, one that's nowhere near the ones
+        // before it starts probation over from itself
+        // unlike the RFC, loss and reordering within the usual window still count as in a row,
+        // a jitter buffer has to put up with them from the first packet on
+        if self.probation > 0 {
+            if in_window {
+                self.probation -= 1;
+            } else {
+                self.probation = MIN_SEQUENTIAL - 1;
+                self.resync(seq);
+                self.received += 1;
+                return true;
+            }
+        }
+
+        if udelta < MAX_DROPOUT {
+            // in order, with permissible gap
+            if seq < self.max_seq {
+                // sequence number wrapped - count another 64K cycle
+                self.cycles = self.cycles.wrapping_add(RTP_SEQ_MOD);
+            }
+            self.max_seq = seq;
+        } else if udelta <= (RTP_SEQ_MOD - u32::from(MAX_MISORDER)) as u16 {
+            // the sequence number made a very large jump
+            if u32::from(seq) == self.bad_seq {
+                // two sequential packets -- assume that the other side
+                // restarted without telling us so just re-sync
+                // (i.e., pretend this was the first packet)
+                self.resync(seq);
+            } else {
+                self.bad_seq = (u32::from(seq) + 1) & (RTP_SEQ_MOD - 1);
+                return false;
+            }
+        } else {
+            // duplicate or reordered packet
+        }
+        self.received += 1;
+        true
+    }
+
+    // extends a (possibly reordered) seq to 32 bits, relative to the highest one seen so far
+    pub fn extended_seq(&self, seq: u16) -> u32 {
+        let extended_max = self.extended_max_seq();
+        let candidate = (extended_max & !(RTP_SEQ_MOD - 1)) | u32::from(seq);
+
+        if seq > self.max_seq && seq - self.max_seq > (RTP_SEQ_MOD / 2) as u16 {
+            // from before the last wraparound
+            candidate.wrapping_sub(RTP_SEQ_MOD)
+        } else {
+            candidate
+        }
+    }
+
+    pub fn extended_max_seq(&self) -> u32 {
+        self.cycles.wrapping_add(u32::from(self.max_seq))
+    }
+
+    pub fn expected(&self) -> u32 {
+        self.extended_max_seq()
+            .wrapping_sub(self.base_seq)
+            .wrapping_add(1)
+    }
+
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    pub fn on_probation(&self) -> bool {
+        self.probation > 0
+    }
+
+    // bumped every time the seq. numbers start over, anything counting from the old ones has to rebase
+    pub fn resyncs(&self) -> u32 {
+        self.resyncs
+    }
+
+    // cumulative number of packets lost, clamped to the 24 bit signed field of a report block
+    pub fn cumulative_lost(&self) -> i32 {
+        let lost = i64::from(self.expected()) - i64::from(self.received);
+        lost.clamp(-0x80_0000, 0x7f_ffff) as i32
+    }
+
+    // fraction of packets lost since the last call, as a fixed point number with the binary point at the left edge
+    // call this once per reception report, it moves the interval forward
+    pub fn fraction_lost(&mut self) -> u8 {
+        let expected = self.expected();
+        let expected_interval = expected.wrapping_sub(self.expected_prior);
+        self.expected_prior = expected;
+
+        let received_interval = self.received.wrapping_sub(self.received_prior);
+        self.received_prior = self.received;
+
+        let lost_interval = i64::from(expected_interval) - i64::from(received_interval);
+        if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / i64::from(expected_interval)) as u8
+        }
+    }
+
+    // both arguments are in RTP timestamp units, the arrival is from a local clock
+    // so only the differences between transit times matter
+    pub fn update_jitter(&mut self, arrival: u32, timestamp: u32) {
+        let transit = arrival.wrapping_sub(timestamp);
+
+        if let Some(prev_transit) = self.transit {
+            let d = (transit.wrapping_sub(prev_transit) as i32).wrapping_abs() as u32;
+            self.jitter = self
+                .jitter
+                .wrapping_add(d)
+                .wrapping_sub(self.jitter.wrapping_add(8) >> 4);
+        }
+
+        self.transit = Some(transit);
+    }
+
+    pub fn jitter(&self) -> u32 {
+        self.jitter >> 4
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seq_wraparound() {
+        let mut source = RtpSource::new(65534);
+
+        for seq in &[65535u16, 0, 1, 2] {
+            assert!(source.update_seq(*seq));
+        }
+
+        assert_eq!(source.extended_max_seq(), RTP_SEQ_MOD + 2);
+        assert_eq!(source.extended_seq(2), RTP_SEQ_MOD + 2);
+        assert_eq!(source.expected(), 5);
+        assert_eq!(source.received(), 5);
+
+        // a straggler from before the wraparound
+        assert!(source.update_seq(65533));
+        assert_eq!(source.extended_seq(65533), 65533);
+        assert_eq!(source.extended_max_seq(), RTP_SEQ_MOD + 2);
+    }
+
+    #[test]
+    fn test_seq_jump() {
+        let mut source = RtpSource::new(100);
+        assert!(source.update_seq(101));
+        assert!(!source.on_probation());
+
+        // a huge jump is discarded the first time...
+        assert!(!source.update_seq(20000));
+        assert_eq!(source.extended_max_seq(), 101);
+
+        // ...but two in a row means the sender restarted
+        assert!(source.update_seq(20001));
+        assert_eq!(source.extended_max_seq(), 20001);
+        assert_eq!(source.expected(), 1);
+        assert_eq!(source.resyncs(), 1);
+    }
+
+    #[test]
+    fn test_probation() {
+        // a stray first packet, the real stream starts somewhere else entirely
+        let mut source = RtpSource::new(100);
+        assert!(source.on_probation());
+        assert!(source.update_seq(40000));
+        assert_eq!(source.resyncs(), 1);
+        assert!(source.on_probation());
+
+        // reordered, but near enough to count
+        assert!(source.update_seq(40002));
+        assert!(!source.on_probation());
+        assert!(source.update_seq(40001));
+        assert_eq!(source.extended_max_seq(), 40002);
+        assert_eq!(source.expected(), 3);
+        assert_eq!(source.received(), 3);
+    }
+
+    #[test]
+    fn test_loss() {
+        let mut source = RtpSource::new(0);
+
+        // every 4th packet is lost
+        for seq in (1..100u16).filter(|seq| seq % 4 != 0) {
+            assert!(source.update_seq(seq));
+        }
+
+        assert_eq!(source.expected(), 100);
+        assert_eq!(source.cumulative_lost(), 24);
+        assert_eq!(source.fraction_lost(), 61); // 24/100 * 256
+
+        // nothing lost in the next interval
+        for seq in 100..200u16 {
+            assert!(source.update_seq(seq));
+        }
+        assert_eq!(source.fraction_lost(), 0);
+        assert_eq!(source.cumulative_lost(), 24);
+    }
+
+    #[test]
+    fn test_no_jitter() {
+        let mut source = RtpSource::new(0);
+
+        // the transit time never changes
+        for i in 0..100u32 {
+            source.update_jitter(5000 + i * 160, i * 160);
+        }
+
+        assert_eq!(source.jitter(), 0);
+    }
+
+    #[test]
+    fn test_constant_jitter() {
+        let mut source = RtpSource::new(0);
+        source.update_jitter(0, 0);
+
+        // every other packet is 80 units late, so every transit time differs by 80
+        for i in 1..1000u32 {
+            let lag = if i % 2 == 0 { 80 } else { 0 };
+            source.update_jitter(i * 160 + lag, i * 160);
+        }
+
+        // J converges on D with the 1/16 gain
+        assert!(source.jitter() >= 78 && source.jitter() <= 80);
+    }
+
+    #[test]
+    fn test_wrapping_timestamps() {
+        let mut source = RtpSource::new(0);
+
+        for i in 0..100u32 {
+            let timestamp = (u32::MAX - 500).wrapping_add(i * 160);
+            source.update_jitter(i * 160, timestamp);
+        }
+
+        assert_eq!(source.jitter(), 0);
+    }
+}
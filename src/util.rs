@@ -1,5 +1,19 @@
-use crate::rtp::JITTERS_SAMPLE_RATE;
+use std::time::Instant;
 
-pub fn samples_to_ms(samples: usize, channels: u16) -> f64 {
-    (1000.0 / (f64::from(JITTERS_SAMPLE_RATE))) * (samples as f64 / (f64::from(2 * channels)))
+// samples are sample frames, one sample per channel, which is what RTP timestamps count
+pub fn samples_to_ms(samples: usize, clock_rate: u32) -> f64 {
+    (1000.0 / (f64::from(clock_rate))) * samples as f64
+}
+
+pub fn ms_to_samples(ms: f64, clock_rate: u32) -> i64 {
+    (ms * (f64::from(clock_rate) / 1000.0)).round() as i64
+}
+
+// negative if the instant is before the reference
+pub fn ms_since(reference: Instant, instant: Instant) -> f64 {
+    if instant >= reference {
+        instant.duration_since(reference).as_secs_f64() * 1000.0
+    } else {
+        -(reference.duration_since(instant).as_secs_f64() * 1000.0)
+    }
 }